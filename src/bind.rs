@@ -0,0 +1,52 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::SetThreadLocal;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+/// Restores the value replaced by `SetThreadLocal::bind()` when dropped, including while
+/// unwinding.
+#[must_use = "the previous value is restored as soon as the guard is dropped"]
+pub struct BindGuard<'a, T, S: SetThreadLocal<T> + ?Sized + 'a> {
+    local: &'a S,
+    previous: Option<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T, S: SetThreadLocal<T> + ?Sized> BindGuard<'a, T, S> {
+    pub(crate) fn new(local: &'a S, value: T) -> Self {
+        let previous = local.take();
+        if local.set(value).is_err() {
+            unreachable!();
+        }
+        BindGuard {
+            local,
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, S: SetThreadLocal<T> + ?Sized> Drop for BindGuard<'a, T, S> {
+    fn drop(&mut self) {
+        self.local.take();
+        if let Some(previous) = self.previous.take() {
+            if self.local.set(previous).is_err() {
+                unreachable!();
+            }
+        }
+    }
+}
+
+impl<'a, T, S: SetThreadLocal<T> + ?Sized> Debug for BindGuard<'a, T, S> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("BindGuard")
+            .field("had_previous", &self.previous.is_some())
+            .finish()
+    }
+}
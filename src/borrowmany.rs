@@ -0,0 +1,141 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::RefThreadLocal;
+use refmanager::{Ref, RefMut};
+use std::fmt::{Display, Formatter};
+
+/// The error returned by `try_borrow_many!` when one of the statics cannot be borrowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowManyError {
+    index: usize,
+    name: &'static str,
+    description: &'static str,
+    unset: bool,
+}
+
+impl BorrowManyError {
+    /// Returns the position of the static that could not be borrowed, starting at 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the static that could not be borrowed, as written in the macro call.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns `true` if the borrow failed because the value has not been set yet.
+    pub fn is_unset(&self) -> bool {
+        self.unset
+    }
+}
+
+impl Display for BorrowManyError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "cannot borrow `{}` (argument {}): {}",
+            self.name,
+            self.index + 1,
+            self.description
+        )
+    }
+}
+
+impl std::error::Error for BorrowManyError {}
+
+#[doc(hidden)]
+pub fn try_borrow_nth<'a, T, S>(
+    local: &S,
+    index: usize,
+    name: &'static str,
+) -> Result<Ref<'a, T>, BorrowManyError>
+where
+    S: RefThreadLocal<T> + ?Sized,
+{
+    local.try_borrow().map_err(|err| BorrowManyError {
+        index,
+        name,
+        description: err.description(),
+        unset: err.is_unset(),
+    })
+}
+
+#[doc(hidden)]
+pub fn try_borrow_mut_nth<'a, T, S>(
+    local: &S,
+    index: usize,
+    name: &'static str,
+) -> Result<RefMut<'a, T>, BorrowManyError>
+where
+    S: RefThreadLocal<T> + ?Sized,
+{
+    local.try_borrow_mut().map_err(|err| BorrowManyError {
+        index,
+        name,
+        description: err.description(),
+        unset: err.is_unset(),
+    })
+}
+
+/// Borrows several statics at once, some of them mutably, and returns a tuple of `Ref`s and
+/// `RefMut`s in the same order.
+///
+/// If one of the borrows fails, the borrows already taken are released and the error tells
+/// which static conflicted.
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate ref_thread_local;
+/// use ref_thread_local::RefThreadLocal;
+///
+/// ref_thread_local! {
+///     static managed INPUT: Vec<u32> = vec![1, 2, 3];
+///     static managed OUTPUT: Vec<u32> = Vec::new();
+/// }
+///
+/// # fn main() {
+/// let (input, mut output) = try_borrow_many!(&INPUT, &mut OUTPUT).unwrap();
+/// output.extend(input.iter().map(|x| x * 2));
+/// drop(input);
+///
+/// let err = try_borrow_many!(&mut INPUT, &OUTPUT).unwrap_err();
+/// assert_eq!(err.name(), "OUTPUT");
+/// # }
+/// ```
+#[macro_export(local_inner_macros)]
+macro_rules! try_borrow_many {
+  ($($t:tt)+) => {
+    $crate::_borrow_many_internal!(@ARGS [] (0usize) $($t)+)
+  };
+}
+
+/// Like `try_borrow_many!`, but panics if one of the statics cannot be borrowed.
+#[macro_export(local_inner_macros)]
+macro_rules! borrow_many {
+  ($($t:tt)+) => {
+    match $crate::try_borrow_many!($($t)+) {
+      ::std::result::Result::Ok(borrows) => borrows,
+      ::std::result::Result::Err(err) => ::std::panic!("{}", err),
+    }
+  };
+}
+
+#[macro_export(local_inner_macros)]
+#[doc(hidden)]
+macro_rules! _borrow_many_internal {
+  (@ARGS [$($done:tt)*] ($i:expr) &mut $e:expr $(, $($t:tt)*)?) => {
+    $crate::_borrow_many_internal!(@ARGS [$($done)* ($crate::try_borrow_mut_nth(&$e, $i, ::std::stringify!($e))?)] ($i + 1) $($($t)*)?)
+  };
+  (@ARGS [$($done:tt)*] ($i:expr) & $e:expr $(, $($t:tt)*)?) => {
+    $crate::_borrow_many_internal!(@ARGS [$($done)* ($crate::try_borrow_nth(&$e, $i, ::std::stringify!($e))?)] ($i + 1) $($($t)*)?)
+  };
+  (@ARGS [$($done:tt)*] ($i:expr)) => {
+    (|| ::std::result::Result::Ok::<_, $crate::BorrowManyError>(($($done,)*)))()
+  };
+}
@@ -0,0 +1,70 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{CellThreadLocal, RefThreadLocal};
+use group::ManagedStatic;
+use refmanager::{RefManager, RefManagerDataGuard};
+use std::thread::LocalKey;
+
+// A `static cell` shares the storage of a `static managed` one, but never hands out
+// references, so the value can be copied in and out without looking at the borrow count.
+#[derive(Debug)]
+pub struct CellManager<T: Copy + 'static> {
+    ref_manager: RefManager<T>,
+}
+
+impl<T: Copy> CellManager<T> {
+    pub fn new(local_key: &'static LocalKey<RefManagerDataGuard<T>>, init_func: fn() -> T) -> Self {
+        CellManager {
+            ref_manager: RefManager::new(local_key, init_func),
+        }
+    }
+
+    /// Records the value for `destroy_all_on_current_thread()`, like
+    /// `RefManager::registered()`.
+    pub fn registered(self, member: &'static dyn ManagedStatic) -> Self {
+        CellManager {
+            ref_manager: self.ref_manager.registered(member),
+        }
+    }
+}
+
+impl<T: Copy> CellThreadLocal<T> for CellManager<T> {
+    fn initialize(&self) -> Result<(), ()> {
+        self.ref_manager.initialize()
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        self.ref_manager.destroy()
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.ref_manager.is_initialized()
+    }
+
+    fn get(&self) -> T {
+        unsafe { *self.ref_manager.get_initialized_value_ptr() }
+    }
+
+    fn set(&self, value: T) {
+        unsafe { *self.ref_manager.get_initialized_value_ptr() = value }
+    }
+
+    fn replace(&self, value: T) -> T {
+        std::mem::replace(
+            unsafe { &mut *self.ref_manager.get_initialized_value_ptr() },
+            value,
+        )
+    }
+
+    fn update<F: FnOnce(T) -> T>(&self, f: F) -> T {
+        // `f` may destroy or reinitialize the value, so look the slot up again afterwards
+        let value = f(self.get());
+        self.set(value);
+        value
+    }
+}
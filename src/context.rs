@@ -0,0 +1,212 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{SetThreadLocal, StaticInfo};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::thread::{self, JoinHandle};
+
+/// A static whose value can be carried to another thread by a `Context`.
+///
+/// Implemented by every `static managed` and `static once` variable whose type is
+/// `Clone + Send`.
+pub trait ContextLocal: Sync {
+    #[doc(hidden)]
+    fn capture_value(&'static self) -> Option<CapturedValue>;
+}
+
+impl<S> ContextLocal for S
+where
+    S: StaticInfo + SetThreadLocal<<S as StaticInfo>::Value> + Sync + 'static,
+    S::Value: Clone + Send + 'static,
+{
+    fn capture_value(&'static self) -> Option<CapturedValue> {
+        if !self.is_initialized() {
+            return None;
+        }
+        let value = self.borrow().clone();
+        Some(CapturedValue(Box::new(CapturedStatic {
+            local: self,
+            value,
+        })))
+    }
+}
+
+#[doc(hidden)]
+pub struct CapturedValue(Box<dyn Captured>);
+
+trait Captured: Send {
+    fn clone_box(&self) -> Box<dyn Captured>;
+    fn install(self: Box<Self>) -> Box<dyn Restore>;
+}
+
+trait Restore {
+    fn restore(self: Box<Self>);
+}
+
+struct CapturedStatic<S: StaticInfo + 'static> {
+    local: &'static S,
+    value: S::Value,
+}
+
+impl<S> Captured for CapturedStatic<S>
+where
+    S: StaticInfo + SetThreadLocal<<S as StaticInfo>::Value> + Sync + 'static,
+    S::Value: Clone + Send + 'static,
+{
+    fn clone_box(&self) -> Box<dyn Captured> {
+        Box::new(CapturedStatic {
+            local: self.local,
+            value: self.value.clone(),
+        })
+    }
+
+    fn install(self: Box<Self>) -> Box<dyn Restore> {
+        let previous = self.local.take();
+        if self.local.set(self.value).is_err() {
+            unreachable!();
+        }
+        Box::new(RestoreStatic {
+            local: self.local,
+            previous,
+        })
+    }
+}
+
+struct RestoreStatic<S: StaticInfo + 'static> {
+    local: &'static S,
+    previous: Option<S::Value>,
+}
+
+impl<S> Restore for RestoreStatic<S>
+where
+    S: StaticInfo + SetThreadLocal<<S as StaticInfo>::Value> + 'static,
+{
+    fn restore(self: Box<Self>) {
+        self.local.take();
+        if let Some(previous) = self.previous {
+            if self.local.set(previous).is_err() {
+                unreachable!();
+            }
+        }
+    }
+}
+
+/// A snapshot of the values of some statics on one thread, which can be sent to another
+/// thread and attached there.
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate ref_thread_local;
+/// use ref_thread_local::{Context, RefThreadLocal};
+///
+/// ref_thread_local! {
+///     static managed REQUEST_ID: u64 = 0;
+/// }
+///
+/// # fn main() {
+/// *REQUEST_ID.borrow_mut() = 42;
+/// let context = Context::capture(&[&REQUEST_ID]);
+/// std::thread::spawn(move || {
+///     let _guard = context.attach();
+///     assert_eq!(*REQUEST_ID.borrow(), 42);
+/// })
+/// .join()
+/// .unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Context {
+    values: Vec<CapturedValue>,
+}
+
+impl Clone for CapturedValue {
+    fn clone(&self) -> Self {
+        CapturedValue(self.0.clone_box())
+    }
+}
+
+impl Context {
+    /// Clones the current thread's values of `statics`. Statics that are not initialized on
+    /// the current thread are skipped, and stay untouched when the context is attached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the values is mutably borrowed.
+    pub fn capture(statics: &[&'static dyn ContextLocal]) -> Context {
+        Context {
+            values: statics
+                .iter()
+                .filter_map(|local| local.capture_value())
+                .collect(),
+        }
+    }
+
+    /// Installs the captured values on the current thread until the returned guard is
+    /// dropped, which restores the values (or the uninitialized state) they replaced.
+    /// Changes made to the installed values are discarded at that point.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `SetThreadLocal::take()` if one of the replaced values is borrowed,
+    /// frozen, pinned or shared by a `LocalRc`, and likewise when the guard is dropped.
+    pub fn attach(self) -> ContextGuard {
+        ContextGuard {
+            restores: self
+                .values
+                .into_iter()
+                .map(|value| value.0.install())
+                .collect(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Debug for Context {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("Context")
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+/// Restores the values replaced by `Context::attach()` when dropped, including while
+/// unwinding.
+#[must_use = "the context is detached as soon as the guard is dropped"]
+pub struct ContextGuard {
+    restores: Vec<Box<dyn Restore>>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        while let Some(restore) = self.restores.pop() {
+            restore.restore();
+        }
+    }
+}
+
+impl Debug for ContextGuard {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("ContextGuard")
+            .field("values", &self.restores.len())
+            .finish()
+    }
+}
+
+/// Spawns a thread that runs `f` with the current thread's values of `statics` attached.
+pub fn spawn_with_context<F, R>(statics: &[&'static dyn ContextLocal], f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let context = Context::capture(statics);
+    thread::spawn(move || {
+        let _guard = context.attach();
+        f()
+    })
+}
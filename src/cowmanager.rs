@@ -0,0 +1,99 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{RefThreadLocal, SetThreadLocal};
+use refmanager::{BorrowError, BorrowMutError, FrozenRef, Ref, RefManager, RefMut};
+use std::sync::Arc;
+
+// A `static managed(cow)` stores an `Arc<T>` per thread, which starts out as a clone of the
+// process-wide value and is only copied by the first `borrow_mut()`.
+#[derive(Debug)]
+pub struct CowManager<T: Clone + 'static> {
+    ref_manager: RefManager<Arc<T>>,
+}
+
+impl<T: Clone> CowManager<T> {
+    pub fn new(ref_manager: RefManager<Arc<T>>) -> Self {
+        CowManager { ref_manager }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn freeze(&self) -> Result<(), ()> {
+        self.ref_manager.freeze()
+    }
+
+    pub unsafe fn unfreeze(&self) {
+        self.ref_manager.unfreeze()
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.ref_manager.is_frozen()
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        self.ref_manager.is_borrowed()
+    }
+
+    pub fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R {
+        self.ref_manager
+            .with_frozen(|value| f(FrozenRef::map(value, |value| &**value)))
+    }
+}
+
+fn unwrap_or_clone<T: Clone>(value: Arc<T>) -> T {
+    Arc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+}
+
+impl<T: Clone> RefThreadLocal<T> for CowManager<T> {
+    fn initialize(&self) -> Result<(), ()> {
+        self.ref_manager.initialize()
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        self.ref_manager.destroy()
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.ref_manager.is_initialized()
+    }
+
+    fn generation(&self) -> usize {
+        self.ref_manager.generation()
+    }
+
+    fn borrow<'a>(&self) -> Ref<'a, T> {
+        Ref::map(self.ref_manager.borrow(), |value| &**value)
+    }
+
+    fn borrow_mut<'a>(&self) -> RefMut<'a, T> {
+        RefMut::map(self.ref_manager.borrow_mut(), Arc::make_mut)
+    }
+
+    fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        self.ref_manager
+            .try_borrow()
+            .map(|value| Ref::map(value, |value| &**value))
+    }
+
+    fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        self.ref_manager
+            .try_borrow_mut()
+            .map(|value| RefMut::map(value, Arc::make_mut))
+    }
+}
+
+impl<T: Clone> SetThreadLocal<T> for CowManager<T> {
+    fn set(&self, value: T) -> Result<(), T> {
+        self.ref_manager
+            .set(Arc::new(value))
+            .map_err(unwrap_or_clone)
+    }
+
+    fn take(&self) -> Option<T> {
+        self.ref_manager.take().map(unwrap_or_clone)
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Incremented in the child of every `fork()` once `register_atfork()` has been called. The
+// child only has a copy of the forking thread, whose values are reset on their next access.
+static FORK_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn fork_epoch() -> usize {
+    FORK_EPOCH.load(Ordering::Acquire)
+}
+
+#[cfg(unix)]
+mod atfork {
+    use super::FORK_EPOCH;
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+    use std::sync::Once;
+
+    type Handler = Option<unsafe extern "C" fn()>;
+
+    extern "C" {
+        fn pthread_atfork(prepare: Handler, parent: Handler, child: Handler) -> c_int;
+    }
+
+    // Runs in the child, where only async-signal-safe operations are allowed.
+    unsafe extern "C" fn child_after_fork() {
+        FORK_EPOCH.fetch_add(1, Ordering::AcqRel);
+    }
+
+    static REGISTER: Once = Once::new();
+
+    pub(crate) fn register() {
+        REGISTER.call_once(|| {
+            if unsafe { pthread_atfork(None, None, Some(child_after_fork)) } != 0 {
+                panic!("failed to register a fork handler");
+            }
+        });
+    }
+}
+
+// Makes `fork_epoch()` follow the forks of the process.
+pub(crate) fn register_atfork() {
+    #[cfg(unix)]
+    atfork::register();
+}
@@ -0,0 +1,339 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{FreezeThreadLocal, StaticInfo};
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+
+/// A `static managed`, `static once`, `static cell` or `static managed group` variable, as
+/// seen by groups and by `destroy_all_on_current_thread()`.
+#[doc(hidden)]
+#[allow(clippy::result_unit_err)]
+pub trait ManagedStatic: Sync {
+    fn static_type_id(&self) -> TypeId;
+    fn static_name(&self) -> &'static str;
+    fn static_groups(&self) -> &'static [&'static str];
+    fn static_status(&self) -> StaticStatus;
+    fn initialize_static(&self) -> Result<(), ()>;
+    fn destroy_static(&self) -> Result<(), ()>;
+}
+
+impl<S> ManagedStatic for S
+where
+    S: StaticInfo + FreezeThreadLocal<<S as StaticInfo>::Value> + Sync + 'static,
+{
+    fn static_type_id(&self) -> TypeId {
+        TypeId::of::<S>()
+    }
+
+    fn static_name(&self) -> &'static str {
+        S::NAME
+    }
+
+    fn static_groups(&self) -> &'static [&'static str] {
+        S::GROUPS
+    }
+
+    fn static_status(&self) -> StaticStatus {
+        let initialized = self.is_initialized();
+        let frozen = initialized && self.is_frozen();
+        StaticStatus {
+            name: S::NAME,
+            initialized,
+            frozen,
+            borrowed: initialized && self.is_borrowed(),
+        }
+    }
+
+    fn initialize_static(&self) -> Result<(), ()> {
+        self.initialize()
+    }
+
+    fn destroy_static(&self) -> Result<(), ()> {
+        self.destroy()
+    }
+}
+
+impl Debug for dyn ManagedStatic {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(self.static_name())
+    }
+}
+
+/// The state of a static on the current thread, as returned by `Group::report()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticStatus {
+    pub name: &'static str,
+    pub initialized: bool,
+    pub frozen: bool,
+    /// `true` if the value is currently borrowed, so that it cannot be destroyed.
+    pub borrowed: bool,
+}
+
+impl StaticStatus {
+    fn is_busy(&self) -> bool {
+        self.frozen || self.borrowed
+    }
+}
+
+thread_local! {
+    // The statics initialized on this thread, in initialization order.
+    static INITIALIZED_STATICS: RefCell<Vec<&'static dyn ManagedStatic>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+// The generated types are zero-sized, so their statics may share an address.
+fn same_static(a: &dyn ManagedStatic, b: &dyn ManagedStatic) -> bool {
+    a.static_type_id() == b.static_type_id()
+}
+
+// Called whenever `member` gets a value on the current thread.
+pub(crate) fn register(member: &'static dyn ManagedStatic) {
+    let _ = INITIALIZED_STATICS.try_with(|statics| {
+        let mut statics = statics.borrow_mut();
+        statics.retain(|&other| !same_static(other, member));
+        statics.push(member);
+    });
+}
+
+fn initialized_statics() -> Vec<&'static dyn ManagedStatic> {
+    INITIALIZED_STATICS
+        .try_with(|statics| statics.borrow().clone())
+        .unwrap_or_default()
+}
+
+// Destroys the initialized statics accepted by `filter` in reverse initialization order,
+// skipping and returning the names of those that are borrowed or frozen.
+fn destroy_matching<F>(filter: F) -> Vec<&'static dyn ManagedStatic>
+where
+    F: Fn(&dyn ManagedStatic) -> bool,
+{
+    let mut busy = Vec::new();
+    let mut released = Vec::new();
+    for member in initialized_statics().into_iter().rev() {
+        if !filter(member) {
+            continue;
+        }
+        let status = member.static_status();
+        if status.is_busy() {
+            busy.push(member);
+        } else {
+            if status.initialized {
+                let _ = member.destroy_static();
+            }
+            released.push(member);
+        }
+    }
+    // statics initialized again by the destructors stay in the list
+    released.retain(|member| !member.static_status().initialized);
+    let _ = INITIALIZED_STATICS.try_with(|statics| {
+        statics
+            .borrow_mut()
+            .retain(|&member| !released.iter().any(|&r| same_static(r, member)));
+    });
+    busy
+}
+
+fn names(statics: Vec<&'static dyn ManagedStatic>) -> Result<(), Vec<&'static str>> {
+    if statics.is_empty() {
+        Ok(())
+    } else {
+        Err(statics.iter().map(|member| member.static_name()).collect())
+    }
+}
+
+/// Destroys every `static managed`, `static once`, `static cell` and `static managed group`
+/// value of the current thread, in reverse initialization order.
+///
+/// Values that are borrowed or frozen, including groups with a borrowed field, are left
+/// alone, and their names are returned as the error.
+pub fn destroy_all_on_current_thread() -> Result<(), Vec<&'static str>> {
+    names(destroy_matching(|_| true))
+}
+
+// Destructors may initialize other statics, which are destroyed by the next pass, up to
+// this many passes.
+const MAX_SHUTDOWN_PASSES: usize = 16;
+
+/// Destroys every value of the current thread like `destroy_all_on_current_thread()`,
+/// including the values that their destructors initialize, e.g. at the end of `main()`,
+/// whose thread-local destructors may never run.
+///
+/// Values that are borrowed or frozen are left alone, and their names are returned as the
+/// error. Statics accessed afterwards are initialized again.
+pub fn shutdown() -> Result<(), Vec<&'static str>> {
+    for _ in 0..MAX_SHUTDOWN_PASSES {
+        let busy = destroy_matching(|_| true);
+        if initialized_statics().len() == busy.len() {
+            return names(busy);
+        }
+    }
+    names(initialized_statics())
+}
+
+#[cfg(any(unix, windows))]
+mod at_exit {
+    use super::shutdown;
+    use std::io::Write;
+    use std::os::raw::c_int;
+    use std::panic;
+    use std::sync::Once;
+
+    extern "C" {
+        fn atexit(callback: extern "C" fn()) -> c_int;
+    }
+
+    extern "C" fn shutdown_on_exit() {
+        // unwinding out of the callback would abort the process
+        let result = panic::catch_unwind(shutdown);
+        if let Ok(Err(busy)) = result {
+            let _ = writeln!(
+                std::io::stderr(),
+                "ref_thread_local: still borrowed or frozen at exit: {}",
+                busy.join(", ")
+            );
+        }
+    }
+
+    static REGISTER: Once = Once::new();
+
+    pub(super) fn register() -> Result<(), ()> {
+        let mut result = Ok(());
+        REGISTER.call_once(|| {
+            if unsafe { atexit(shutdown_on_exit) } != 0 {
+                result = Err(());
+            }
+        });
+        result
+    }
+}
+
+/// Makes `exit()`, including returning from `main()`, call `shutdown()` on the exiting
+/// thread, and report on stderr the statics it could not destroy. Calling it again has no
+/// effect.
+///
+/// Fails if the handler cannot be registered, or on platforms without `atexit()`.
+#[allow(clippy::result_unit_err)]
+pub fn shutdown_at_exit() -> Result<(), ()> {
+    #[cfg(any(unix, windows))]
+    return at_exit::register();
+    #[cfg(not(any(unix, windows)))]
+    Err(())
+}
+
+const fn same_name(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Fails to compile, through the constant it is evaluated in, if one of the statics of an
+// invocation of `ref_thread_local!` is tagged with a group that it does not declare.
+#[doc(hidden)]
+pub const fn check_group_tags(tags: &[&[&str]], groups: &[&str]) {
+    let mut i = 0;
+    while i < tags.len() {
+        let mut j = 0;
+        while j < tags[i].len() {
+            let mut declared = false;
+            let mut k = 0;
+            while k < groups.len() {
+                declared = declared || same_name(tags[i][j], groups[k]);
+                k += 1;
+            }
+            if !declared {
+                panic!("statics can only be tagged with groups declared by `static group` in the same `ref_thread_local!` invocation");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// The handle declared by `static group NAME;` in `ref_thread_local!`, whose members are the
+/// statics tagged with `#[group(NAME)]` in the same invocation of the macro.
+///
+/// All operations only concern the current thread.
+#[derive(Clone, Copy, Debug)]
+pub struct Group {
+    name: &'static str,
+    // the statics of the invocation that can have a group, which `members()` narrows down
+    members: &'static [&'static dyn ManagedStatic],
+}
+
+impl Group {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, members: &'static [&'static dyn ManagedStatic]) -> Self {
+        Group { name, members }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn members(&self) -> impl Iterator<Item = &'static dyn ManagedStatic> + '_ {
+        self.members
+            .iter()
+            .cloned()
+            .filter(move |member| member.static_groups().contains(&self.name))
+    }
+
+    fn contains(&self, member: &dyn ManagedStatic) -> bool {
+        self.members().any(|other| same_static(other, member))
+    }
+
+    /// Initializes every member that is not initialized on the current thread yet.
+    /// `static once` members are skipped, as they can only be `set()`.
+    pub fn initialize_all(&self) {
+        for member in self.members() {
+            if !member.static_status().initialized {
+                let _ = member.initialize_static();
+            }
+        }
+    }
+
+    /// Destroys every member initialized on the current thread, in reverse initialization
+    /// order. Members that are borrowed or frozen are left alone, and their names are
+    /// returned as the error.
+    pub fn destroy_all(&self) -> Result<(), Vec<&'static str>> {
+        names(destroy_matching(|member| self.contains(member)))
+    }
+
+    /// Replaces every member initialized on the current thread with a newly initialized
+    /// value, like `destroy_all()` followed by initializing the destroyed members again.
+    /// `static once` members are left unset.
+    pub fn reset_all(&self) -> Result<(), Vec<&'static str>> {
+        let members: Vec<_> = initialized_statics()
+            .into_iter()
+            .filter(|&member| self.contains(member) && member.static_status().initialized)
+            .collect();
+        let busy = destroy_matching(|member| self.contains(member));
+        for member in members {
+            if !busy.iter().any(|&b| same_static(b, member)) {
+                let _ = member.initialize_static();
+            }
+        }
+        names(busy)
+    }
+
+    /// Returns the state on the current thread of every member.
+    pub fn report(&self) -> Vec<StaticStatus> {
+        self.members()
+            .map(|member| member.static_status())
+            .collect()
+    }
+}
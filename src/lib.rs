@@ -23,6 +23,119 @@ ref_thread_local! {
 }
 ```
 
+With the `context` option, the initializer is written as a closure that receives a
+`ThreadContext` describing the current thread, which gives access to its `ThreadId`, its name
+and a small per-process index. `context` comes before any other option:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed(context) NAME: TYPE = |ctx| EXPR;
+}
+```
+
+For thread-local counters and flags of a `Copy` type, `static cell` can be used instead of
+`static managed`:
+
+```ignore
+ref_thread_local! {
+    [pub] static cell NAME: TYPE = EXPR;
+}
+```
+
+Values that only the thread itself can produce are declared without an initializer using
+`static once`, and are provided later by calling `set()`:
+
+```ignore
+ref_thread_local! {
+    [pub] static once NAME: TYPE;
+}
+```
+
+The values of a `static managed(shared)` variable, whose type has to be `Send`, can also be
+reached from other threads through `SharedThreadLocal`, e.g. to collect per-thread statistics:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed(shared) NAME: TYPE = EXPR;
+}
+```
+
+A process-wide value kept in a `GlobalCell<T>` can be mirrored into every thread as an
+`Arc<T>`, which is refreshed on access when a new value has been published:
+
+```ignore
+static GLOBAL: GlobalCell<TYPE> = GlobalCell::new(INIT_FN);
+
+ref_thread_local! {
+    [pub] static mirror NAME: Arc<TYPE> = from(&GLOBAL);
+}
+```
+
+A coordinator thread can also hand work to the thread owning a `static managed(mailbox)`
+value, which runs it on its value the next time it accesses it:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed(mailbox) NAME: TYPE = EXPR;
+}
+```
+
+Large values that most threads only read can be built once per process and shared until a
+thread modifies its copy:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed(cow) NAME: TYPE = EXPR;
+}
+```
+
+A `static managed` declaration can end with `=> MERGE`, a function or closure that receives
+the value by move when it is destroyed or its thread exits, e.g. to fold per-thread
+statistics into a global total:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed NAME: TYPE = EXPR => MERGE;
+}
+```
+
+A `static scoped` variable stores no value of its own. Like the `scoped-tls` crate, it lends
+a reference to a value owned further up the call stack, which needs not be `'static`:
+
+```ignore
+ref_thread_local! {
+    [pub] static scoped NAME: TYPE;
+}
+```
+
+`static managed` and `static once` variables can be tagged with one or more groups, which
+are then handled together through the `Group` declared by `static group` in the same
+invocation:
+
+```ignore
+ref_thread_local! {
+    [pub] static group GROUP_1;
+    [pub] static group GROUP_2;
+    #[group(GROUP_1, GROUP_2)]
+    [pub] static managed NAME: TYPE = EXPR;
+}
+```
+
+Tagging a static with a group declared in another invocation fails to compile, since that
+group could not list it among its members.
+
+Related values that are always used together can share one thread-local slot and one lazy
+initialization as the fields of a `static managed group`:
+
+```ignore
+ref_thread_local! {
+    [pub] static managed group NAME {
+        FIELD_1: TYPE_1 = EXPR_1,
+        FIELD_2: TYPE_2 = EXPR_2,
+    }
+}
+```
+
 Attributes (including doc comments) are supported as well:
 
 ```rust
@@ -51,9 +164,121 @@ thread-local `static` variable on calling `initialize()`, `borrow()`, `borrow_mu
 Like `RefCell`, `borrow()` and `borrow_mut()` don't return reference but instead
 `Ref<'a, T>` or `RefMut<'a, T>`, which manage a borrow count internally.
 
+For a given `static cell NAME: TYPE = EXPR;`, the generated type implements
+`CellThreadLocal<T>` instead, which has the same lazy initialization and `initialize()`,
+`destroy()` and `is_initialized()` lifecycle, but accesses the value like a `Cell` through
+`get()`, `set()`, `replace()` and `update()`, without any borrow counting.
+
+A `static once NAME: TYPE;` implements `RefThreadLocal<T>` as well, but is never initialized
+lazily and its `initialize()` always fails: until `set()` is called on the current thread,
+`try_borrow()` returns an error and `borrow()` panics. `destroy()` makes it settable again.
+`set()` and `take()` come from `SetThreadLocal<T>`, which `static managed` variables
+implement too.
+
+A `static managed group NAME { ... }` implements `StructThreadLocal`, which initializes and
+destroys all fields at once, and each field `NAME.FIELD` is a `StructField<T>` with its own
+borrow count: `NAME.FIELD_1.borrow_mut()` and `NAME.FIELD_2.borrow()` can be held at the same
+time. All fields are stored in a single heap allocation per thread.
+
+A `static scoped NAME: TYPE;` implements `ScopedThreadLocal<T>`: `NAME.set(&value, || ...)`
+makes `value` available to `NAME.with(|value| ...)` on the current thread until the closure
+returns, and `with()` fails outside of `set()`. `TYPE` may be unsized, e.g. `str`.
+
 Like `thread_local!`, variables in `ref_thread_local!` will be dropped normally
 when thread is exiting or `destroy()` is called.
 
+A `static managed(shared)` variable additionally implements `SharedThreadLocal<T>`. Every
+thread's value is registered in a process-wide registry when it is initialized, and
+`for_each_thread()` and `collect()` visit all of them. While a value is visited, its own
+thread waits before borrowing it, so borrows of such variables cost a few atomic operations.
+Shared variables cannot be frozen for good, and `with_frozen()` counts as a borrow of them
+until the closure returns.
+
+Every `static managed`, `static once` and `static cell` value, and every `static managed
+group`, initialized on a thread is remembered in a per-thread list.
+`destroy_all_on_current_thread()` destroys them in reverse initialization order, e.g.
+between jobs on a reused worker thread, and a `static group` does the same for its members
+with `destroy_all()` and `reset_all()`. It also offers `initialize_all()` to warm up a
+thread and `report()` to inspect its members.
+Values that are borrowed or frozen are skipped and reported by name instead of panicking.
+`shutdown()` does the same at the end of `main()`, whose thread-local values may never be
+dropped otherwise, until no value is left, and `shutdown_at_exit()` makes `exit()` call it.
+
+Thread-local values that are created at runtime instead of being declared as `static`s,
+for example one per instance of a struct, are available through `PerThread<T>`. Generic code
+that needs one value per type parameter can use `typed::<T>()` instead, which returns the
+handle of the current thread's value of type `T` in a per-thread map keyed by `TypeId`.
+
+The values of selected statics can be carried over to another thread, e.g. a request id or
+a configuration handed to worker threads: `Context::capture(&[&NAME_1, &NAME_2])` clones them,
+and `attach()` on the destination thread installs them until the returned guard is dropped.
+`spawn_with_context()` does both for a newly spawned thread.
+
+Variables declared with `task_local_managed!` implement `TaskLocalThreadLocal<T>` in addition:
+`NAME.scope(value, future)` returns a future that installs `value` while it is being polled,
+and moves it back out afterwards, so that tasks sharing a thread each see their own value.
+
+Within one thread, `NAME.scoped_set(value, || ...)` and `NAME.bind(value)` of `SetThreadLocal<T>`
+override a value temporarily, like a dynamically scoped variable: the previous value is
+restored when the closure returns or the guard is dropped, even while unwinding.
+
+`try_borrow_many!(&NAME_1, &mut NAME_2, ...)` borrows several statics at once and returns
+a tuple of `Ref`s and `RefMut`s, or releases the borrows it already took and reports which
+static conflicted. `borrow_many!` panics instead.
+
+A `static managed(cow)` variable implements `RefThreadLocal<T>` for a `TYPE` that is `Clone`,
+`Send` and `Sync`. `EXPR` is evaluated once per process into an `Arc<T>`, and each thread's
+value starts out as a clone of that `Arc`: `borrow()` reads the shared value, while the first
+`borrow_mut()` on a thread copies it into a private value. `destroy()` on a thread releases
+its copy, and initializing it again shares the process-wide value again.
+
+A `static managed(mailbox)` variable implements `MailboxThreadLocal<T>`. Its
+`post(thread_id, job)` queues a boxed `FnOnce(&mut T)` for the thread `thread_id` and returns a
+`Completion` that can be waited on. The owning thread runs its jobs in posting order before a
+`borrow()` or `borrow_mut()` that finds the value not borrowed, or when it calls
+`drain_mailbox()`. Only threads that currently hold a value can receive jobs, and jobs posted
+to other threads are dropped, as are the jobs left when a thread destroys its value or exits,
+and those of a value that has been `freeze()`d or pinned, since they cannot write it. Options
+can be combined, as in `managed(shared, mailbox)`.
+
+A `static managed(reset_on_fork)` variable is rebuilt on its next access in the child process
+of a `fork()` on Unix, instead of reusing the value copied from the parent along with the
+state of its borrows, handles or connections. The reset happens like an invalidation by
+`invalidate_all_threads()`: the copied value is dropped, or merged, and the initializer runs
+again, unless the value is borrowed at that point, e.g. by a `Ref` the forking code still holds.
+
+A `static mirror` variable implements `RefThreadLocal<Arc<T>>`. Its value is a clone of the
+`Arc` held by the `GlobalCell`, and each access compares the version of its copy with the
+cell's, so that `GLOBAL.publish(value)` reaches every thread without locking on reads.
+
+`NAME.invalidate_all_threads()` makes the value of every thread stale at once: each thread
+rebuilds its value with the initializer on its next access, unless it is borrowed or frozen
+at that point. It is implemented by every `static managed` variable through
+`EpochThreadLocal<T>`, at the cost of one atomic load per access, except `managed(cow)`
+variables: their initializer only runs once per process, so a thread that wants the shared
+value back calls `take()` instead.
+
+`NAME.share()` returns a `LocalRc<T>`, an `Rc`-like handle to the current value that cannot
+leave the thread. The value lives as long as its handles: `destroy()` detaches the variable
+from a shared value instead of dropping it, and the next access initializes a new one.
+`NAME.downgrade()` returns a `LocalWeak<T>` instead, which does not keep the value alive:
+its `upgrade()` borrows the value only while the variable still holds the same one, as told
+by the `generation()` counter bumped by every initialization and destruction.
+
+Values are stored in a heap allocation that does not move until they are destroyed.
+`NAME.borrow_pin()` borrows the value as a `Pin<RefMut<T>>`, for self-referential or `!Unpin`
+state, after which the value can no longer be borrowed mutably without pinning, nor moved out.
+`NAME.as_ptr()` and `NAME.as_mut_ptr()` return raw pointers to it, valid until it is dropped.
+
+Tables that are built once and then only read can be frozen through `FreezeThreadLocal`.
+`NAME.with_frozen(|table| ...)` hands the closure a `FrozenRef<T>`, which can be copied and
+read without touching the borrow count. The value refuses `borrow_mut()` and `destroy()`
+until the closure returns, and is writable again afterwards. `NAME.freeze()` freezes it
+until further notice instead, after which `borrow()` itself skips the borrow count. Its
+references are not bound to any scope, so a frozen value is leaked when its thread exits,
+and `destroy_all_on_current_thread()` and `shutdown()` report it as busy, unless it is
+unfrozen first by the unsafe `NAME.unfreeze()`, once none of those references is alive.
+
 # Example
 
 Using the macro:
@@ -75,13 +300,14 @@ fn main() {
 
 # Additional Runtime Resource Usage Compared to `thread_local!`
 In current version:
-* For each `static` variable in `ref_thread_local!`: 3 pointer variables, 1 `Cell<isize>`, 1 heap allocation.
+* For each `static` variable in `ref_thread_local!`: 2 pointer variables, 1 generation count, 1 heap allocation holding the value along with 1 borrow count, 1 freeze count, 1 epoch, 1 share count, 3 `Cell<bool>` and 1 pointer to the optional state below, and 1 entry in the thread's list of initialized values.
+* For each `static managed(shared)` variable and variable with a merge function: 1 more heap allocation for that optional state, plus 1 for the atomic borrow count of a shared variable.
 * For each reference: 1 reference
 * For each borrow: some borrow count operations, some function call (may be inlined)
 
 */
 
-#![doc(html_root_url = "https://docs.rs/ref_thread_local/0.1.0")]
+#![doc(html_root_url = "https://docs.rs/ref_thread_local/0.2.0")]
 
 #[doc(hidden)]
 pub use std::ops::Deref as __Deref;
@@ -89,43 +315,705 @@ pub use std::ops::Deref as __Deref;
 pub mod refmanager;
 #[doc(hidden)]
 pub use self::refmanager::*;
-pub use self::refmanager::{Ref, RefMut};
+pub use self::refmanager::{FrozenRef, Ref, RefManager, RefManagerDataGuard, RefMut};
+#[doc(hidden)]
+pub mod cellmanager;
+#[doc(hidden)]
+pub use self::cellmanager::*;
+#[doc(hidden)]
+pub mod oncemanager;
+#[doc(hidden)]
+pub use self::oncemanager::*;
+#[doc(hidden)]
+pub mod cowmanager;
+#[doc(hidden)]
+pub use self::cowmanager::*;
+#[doc(hidden)]
+pub mod scopedmanager;
+#[doc(hidden)]
+pub use self::scopedmanager::*;
+#[doc(hidden)]
+pub mod structmanager;
+pub use self::structmanager::StructField;
+#[doc(hidden)]
+pub use self::structmanager::*;
+mod fork;
+mod mailbox;
+pub use self::mailbox::{Completion, Mailbox};
+mod mirror;
+pub use self::mirror::GlobalCell;
+mod borrowmany;
+pub use self::borrowmany::BorrowManyError;
+#[doc(hidden)]
+pub use self::borrowmany::{try_borrow_mut_nth, try_borrow_nth};
+mod threadcontext;
+pub use self::threadcontext::ThreadContext;
+mod perthread;
+pub use self::perthread::PerThread;
+mod typed;
+pub use self::typed::{register_typed_initializer, typed, Typed};
+mod tasklocal;
+pub use self::tasklocal::TaskScope;
+mod shared;
+pub use self::shared::SharedRegistry;
+mod bind;
+pub use self::bind::BindGuard;
+mod localrc;
+pub use self::localrc::{LocalRc, LocalWeak};
+mod context;
+pub use self::context::{spawn_with_context, Context, ContextGuard, ContextLocal};
+mod group;
+#[doc(hidden)]
+pub use self::group::{check_group_tags, ManagedStatic};
+pub use self::group::{
+    destroy_all_on_current_thread, shutdown, shutdown_at_exit, Group, StaticStatus,
+};
 
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::ThreadId;
+
+#[allow(clippy::result_unit_err)]
 pub trait RefThreadLocal<T> {
     fn initialize(&self) -> Result<(), ()>;
     fn destroy(&self) -> Result<(), ()>;
     fn is_initialized(&self) -> bool;
+    /// Returns the generation of the slot on the current thread, which starts at 0 and is
+    /// incremented whenever a value is initialized or set, and whenever it is destroyed or
+    /// taken. Two calls return the same number only if the value has stayed in place.
+    fn generation(&self) -> usize;
     fn borrow<'a>(&self) -> Ref<'a, T>;
     fn borrow_mut<'a>(&self) -> RefMut<'a, T>;
     fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError>;
     fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError>;
 }
 
+/// Implemented by `static managed` and `static once` variables, whose value can be provided
+/// and moved out by the thread itself.
+pub trait SetThreadLocal<T>: RefThreadLocal<T> {
+    /// Stores `value` if the variable is not initialized on the current thread, without
+    /// running its initializer, or hands it back.
+    fn set(&self, value: T) -> Result<(), T>;
+    /// Moves the value out, leaving the variable uninitialized on the current thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is borrowed or frozen, like `destroy()`, and also if it is
+    /// pinned or shared by a `LocalRc`, as it cannot be moved out of its allocation then.
+    fn take(&self) -> Option<T>;
+
+    /// Replaces the value on the current thread with `value` until the returned guard is
+    /// dropped, which puts back the previous value, or the uninitialized state. Bindings
+    /// nest: each guard restores what was there when it was created.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `take()` if the value is borrowed, frozen, pinned or shared by a
+    /// `LocalRc`, and likewise when the guard is dropped, since the values are moved in and
+    /// out of the variable.
+    fn bind(&self, value: T) -> BindGuard<'_, T, Self>
+    where
+        Self: Sized,
+    {
+        BindGuard::new(self, value)
+    }
+
+    /// Calls `f` with the value on the current thread replaced by `value`, and restores the
+    /// previous one when `f` returns or panics. See `bind()`.
+    fn scoped_set<R, F>(&self, value: T, f: F) -> R
+    where
+        Self: Sized,
+        F: FnOnce() -> R,
+    {
+        let _guard = self.bind(value);
+        f()
+    }
+}
+
+/// Implemented by the `static managed`, `static once` and `static mirror` variables of
+/// `ref_thread_local!`, whose values can be made read-only.
+#[allow(clippy::result_unit_err)]
+pub trait FreezeThreadLocal<T>: RefThreadLocal<T> {
+    /// Makes the value read-only on the current thread for good, initializing it first if
+    /// needed.
+    ///
+    /// While frozen, `borrow()` never fails and hands out references without touching the
+    /// borrow count, while `borrow_mut()` always fails. Fails if the value is mutably
+    /// borrowed or already frozen.
+    ///
+    /// As its references are not counted, and can outlive the thread's other destructors,
+    /// a frozen value cannot be destroyed: unless `unfreeze()` is called, it is leaked when
+    /// its thread exits. Prefer `with_frozen()` when the reads happen within one scope.
+    fn freeze(&self) -> Result<(), ()>;
+    /// Makes a value frozen by `freeze()` writable again on the current thread, so that it
+    /// can be destroyed. Does nothing if it is not frozen, and a running `with_frozen()` still
+    /// keeps it read-only until it returns.
+    ///
+    /// # Safety
+    ///
+    /// No reference returned by `borrow()` or `try_borrow()` while the value was frozen may
+    /// be alive, since they are not counted.
+    unsafe fn unfreeze(&self);
+    /// Returns whether the value is frozen on the current thread, for good or by a running
+    /// `with_frozen()`.
+    fn is_frozen(&self) -> bool;
+    /// Calls `f` with a `FrozenRef` to the value, initializing it first if needed. The value
+    /// is frozen until `f` returns or panics, and can then be written or destroyed again.
+    ///
+    /// The `FrozenRef` does not touch the borrow count, but cannot outlive `f`, while
+    /// `borrow()` keeps counting meanwhile, since its references may. The value of a
+    /// `managed(shared)` variable is borrowed once for the whole call instead, so that other
+    /// threads wait for `f` to return before visiting it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is mutably borrowed.
+    fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R;
+}
+
+/// Describes a `static managed` or `static once` variable generated by `ref_thread_local!`.
+#[doc(hidden)]
+pub trait StaticInfo {
+    type Value;
+    const NAME: &'static str;
+    const GROUPS: &'static [&'static str];
+    /// Returns whether the value is borrowed on the current thread, without side effects.
+    fn is_borrowed(&self) -> bool;
+}
+
+/// Implemented by `static managed(shared)` variables, whose values can be reached from any
+/// thread.
+pub trait SharedThreadLocal<T: Send + 'static>: RefThreadLocal<T> {
+    fn shared_registry(&self) -> &'static SharedRegistry<T>;
+
+    /// Calls `f` with the value of every thread that currently has one.
+    ///
+    /// See `SharedRegistry::for_each_thread` for how this synchronizes with the threads
+    /// owning the values.
+    fn for_each_thread<F>(&self, f: F)
+    where
+        F: FnMut(ThreadId, &T),
+    {
+        self.shared_registry().for_each_thread(f)
+    }
+
+    /// Returns a copy of the value of every thread that currently has one.
+    fn collect(&self) -> Vec<(ThreadId, T)>
+    where
+        T: Clone,
+    {
+        let mut values = Vec::new();
+        self.for_each_thread(|thread_id, value| values.push((thread_id, value.clone())));
+        values
+    }
+}
+
+/// Implemented by `static managed(mailbox)` variables, whose values can be modified by
+/// other threads through jobs that the owning thread runs.
+pub trait MailboxThreadLocal<T: 'static>: RefThreadLocal<T> {
+    fn mailbox(&self) -> &'static Mailbox<T>;
+
+    /// Queues `job` to be run on the value of the thread `thread_id`, the next time that
+    /// thread accesses the value while it is not borrowed, or calls `drain_mailbox()`.
+    ///
+    /// Jobs must not use the same static. Jobs posted to a thread that has not initialized
+    /// the value, or has destroyed it or exited since, are dropped, which makes
+    /// `Completion::wait()` fail, and so are the jobs still queued when the thread destroys
+    /// its value, moves it out, or exits. So are the jobs of a value that has been frozen for
+    /// good or pinned, while those posted during `with_frozen()` wait for it to return.
+    fn post(&self, thread_id: ThreadId, job: Box<dyn FnOnce(&mut T) + Send>) -> Completion {
+        self.mailbox().post(thread_id, job)
+    }
+
+    /// Runs the jobs posted to the current thread right away, initializing the value if
+    /// needed, and returns how many were run, which is none during `with_frozen()` or once
+    /// the value has been frozen for good or pinned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are jobs to run and the value is currently borrowed.
+    fn drain_mailbox(&self) -> usize;
+}
+
+/// Implemented by `static managed` variables, except `managed(cow)` ones, whose values can be
+/// made stale on every thread at once, e.g. after a configuration change.
+pub trait EpochThreadLocal<T>: RefThreadLocal<T> {
+    fn epoch_counter(&self) -> &'static AtomicUsize;
+
+    /// Returns the current epoch, which starts at 0 and is incremented by every call to
+    /// `invalidate_all_threads()`.
+    fn epoch(&self) -> usize {
+        self.epoch_counter().load(Ordering::Acquire)
+    }
+
+    /// Makes the value of every thread stale, and returns the new epoch.
+    ///
+    /// Each thread destroys its stale value and runs the initializer again on its next
+    /// access. A value that is borrowed or frozen at that point is kept until an access
+    /// finds it released.
+    fn invalidate_all_threads(&self) -> usize {
+        self.epoch_counter().fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+/// Implemented by `static managed` variables declared with a merge function:
+/// `static managed NAME: TYPE = EXPR => MERGE;`.
+///
+/// `MERGE` evaluates to a function or closure that receives the value by move. It is called
+/// instead of dropping the value when it is destroyed or when its thread exits, and must
+/// not use `NAME` itself.
+#[allow(clippy::result_unit_err)]
+pub trait MergeThreadLocal<T>: RefThreadLocal<T> {
+    /// Merges the current value and replaces it with a newly initialized one. Fails if the
+    /// value is not initialized on the current thread.
+    fn flush(&self) -> Result<(), ()>;
+}
+
+/// Implemented by `static managed` and `static once` variables, whose values can be held
+/// beyond a `destroy()` through `LocalRc` handles, or referred to through `LocalWeak`
+/// handles.
+pub trait ShareThreadLocal<T>: RefThreadLocal<T> {
+    /// Returns a handle to the value on the current thread, initializing it first if needed.
+    ///
+    /// While handles exist, `destroy()` and the reset of a stale value detach the variable
+    /// from its value instead of dropping it, even if the value is borrowed. The value is
+    /// dropped, or merged, along with its last handle. `take()` panics while handles exist.
+    fn share(&self) -> LocalRc<T>;
+    /// Returns a handle to the value on the current thread, initializing it first if needed,
+    /// that does not keep it alive. See `LocalWeak::upgrade()`.
+    fn downgrade(&self) -> LocalWeak<T>;
+}
+
+/// Implemented by `static managed` and `static once` variables, whose values stay at the same
+/// address from their initialization until they are destroyed.
+pub trait PinThreadLocal<T>: RefThreadLocal<T> {
+    /// Like `try_borrow_mut()`, but pins the value, initializing it first if needed.
+    ///
+    /// Once pinned, the value is never moved again: `borrow_mut()` fails, and `take()` panics,
+    /// until the value is dropped by `destroy()`, by the reset of a stale value or by its
+    /// thread exiting. Its mailbox jobs, if any, are discarded instead of run, so that
+    /// waiting on them fails. Values with a merge function cannot be pinned, as merging
+    /// moves them, and fail to.
+    fn try_borrow_pin<'a>(&self) -> Result<Pin<RefMut<'a, T>>, BorrowMutError>;
+
+    fn borrow_pin<'a>(&self) -> Pin<RefMut<'a, T>> {
+        self.try_borrow_pin()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns a raw pointer to the value on the current thread, initializing it first if
+    /// needed, e.g. to pass it as the context of a C callback.
+    ///
+    /// The pointer stays valid until the value is dropped or moved out: by `destroy()`,
+    /// `take()`, the reset of a stale value on an access to the variable, or the thread
+    /// exiting. While `LocalRc`s exist, it stays valid until the last of them is dropped.
+    /// Accesses through the pointer bypass the borrow count, so the value must not be read
+    /// through it while a `RefMut` exists.
+    fn as_ptr(&self) -> *const T {
+        self.as_mut_ptr()
+    }
+
+    /// Like `as_ptr()`, but the value may also be written through the returned pointer as
+    /// long as no `Ref` or `RefMut` exists, and the value is not frozen. A pinned value must
+    /// not be moved through it.
+    fn as_mut_ptr(&self) -> *mut T;
+}
+
+/// Implemented by the variables of `task_local_managed!`, whose value can follow a future
+/// instead of staying with a thread.
+pub trait TaskLocalThreadLocal<T>: SetThreadLocal<T> + Sized + 'static {
+    /// Returns a future that runs `future` with `value` installed in the variable during each
+    /// of its polls, on whichever thread polls it. Outside of those polls, the variable keeps
+    /// the value of the thread, or of the enclosing task.
+    ///
+    /// `Ref`s and `RefMut`s must not be held across an `.await`: moving the value out after
+    /// the poll panics like `take()` if it is still borrowed, and so does a value that was
+    /// frozen, pinned or shared by a `LocalRc` during the poll.
+    fn scope<F: Future>(&'static self, value: T, future: F) -> TaskScope<T, F, Self> {
+        TaskScope::new(self, value, future)
+    }
+}
+
+/// Implemented by `static scoped` variables, which lend a reference to a value owned by a
+/// caller further up the stack instead of storing one.
+pub trait ScopedThreadLocal<T: ?Sized> {
+    /// Makes `value` visible to `with()` on the current thread while `f` runs. Nested calls
+    /// shadow the outer value, which is visible again once they return or panic.
+    fn set<R, F: FnOnce() -> R>(&self, value: &T, f: F) -> R;
+    /// Calls `f` with the value lent by the innermost running `set()` on the current thread.
+    /// Fails with an error for which `is_unset()` is `true` outside of `set()`.
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<R, BorrowError>;
+    fn is_set(&self) -> bool;
+}
+
+/// Implemented by `static managed group` variables, whose fields share one slot and one
+/// lazy initialization.
+#[allow(clippy::result_unit_err)]
+pub trait StructThreadLocal {
+    fn initialize(&self) -> Result<(), ()>;
+    /// Destroys every field at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any field is borrowed.
+    fn destroy(&self) -> Result<(), ()>;
+    fn is_initialized(&self) -> bool;
+}
+
+/// The `Cell`-like counterpart of `RefThreadLocal` implemented by `static cell` variables.
+#[allow(clippy::result_unit_err)]
+pub trait CellThreadLocal<T: Copy> {
+    fn initialize(&self) -> Result<(), ()>;
+    fn destroy(&self) -> Result<(), ()>;
+    fn is_initialized(&self) -> bool;
+    fn get(&self) -> T;
+    fn set(&self, value: T);
+    fn replace(&self, value: T) -> T;
+    /// Replaces the value with `f` applied to it and returns the new value.
+    fn update<F: FnOnce(T) -> T>(&self, f: F) -> T;
+}
+
 #[macro_export(local_inner_macros)]
 #[doc(hidden)]
 macro_rules! _ref_thread_local_internal {
-  ($(#[$attr:meta])* ($($vis:tt)*) static $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
+  // `$members` lists the statics of the invocation that can be group members, and `$groups`
+  // the `static group` declarations, which are only emitted once all members are known. Each
+  // static is split off in a single step, and expanded by `@ATTRS` on the side, so that long
+  // invocations do not run into the recursion limit.
+  (@NEXT $members:tt [$($decl:tt)*]) => {
+    $crate::_ref_thread_local_internal!(@CHECK $members [$($decl)*]);
+    $($crate::_ref_thread_local_internal!(@GROUP $decl $members);)*
+  };
+  // a static cannot join a group of another invocation, which would not know about it
+  (@CHECK [$($M:ident)*] [$({[$($attr:tt)*] ($($vis:tt)*) $G:ident})*]) => {
+    const _: () = $crate::check_group_tags(
+      &[$(<$M as $crate::StaticInfo>::GROUPS),*],
+      &[$(::std::stringify!($G)),*],
+    );
+  };
+  (@NEXT $members:tt [$($decl:tt)*] $(#[$attr:meta])* $vis:vis static group $G:ident; $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@NEXT $members [$($decl)* {[$(#[$attr])*] ($vis) $G}] $($t)*);
+  };
+  (@NEXT $members:tt $groups:tt $(#[$($attr:tt)*])* $vis:vis static managed group $N:ident { $($f:tt)* } $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static managed group $N { $($f)* });
+    $crate::_ref_thread_local_internal!(@NEXT $members $groups $($t)*);
+  };
+  (@NEXT [$($M:ident)*] $groups:tt $(#[$($attr:tt)*])* $vis:vis static managed (context $(, $opt:ident)*) $N:ident : $T:ty = |$ctx:pat| $e:expr $(=> $m:expr)?; $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static managed (context $(, $opt)*) $N : $T = |$ctx| $e $(=> $m)?;);
+    $crate::_ref_thread_local_internal!(@NEXT [$($M)* $N] $groups $($t)*);
+  };
+  (@NEXT [$($M:ident)*] $groups:tt $(#[$($attr:tt)*])* $vis:vis static mirror $N:ident : $T:ty = from(&$G:expr); $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static mirror $N : $T = from(&$G););
+    $crate::_ref_thread_local_internal!(@NEXT [$($M)* $N] $groups $($t)*);
+  };
+  (@NEXT $members:tt $groups:tt $(#[$($attr:tt)*])* $vis:vis static scoped $N:ident : $T:ty; $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static scoped $N : $T;);
+    $crate::_ref_thread_local_internal!(@NEXT $members $groups $($t)*);
+  };
+  (@NEXT $members:tt $groups:tt $(#[$($attr:tt)*])* $vis:vis static cell $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static cell $N : $T = $e;);
+    $crate::_ref_thread_local_internal!(@NEXT $members $groups $($t)*);
+  };
+  // `static managed`, with or without options, and `static once`
+  (@NEXT [$($M:ident)*] $groups:tt $(#[$($attr:tt)*])* $vis:vis static $kind:ident $(($($opt:ident),*))? $N:ident : $T:ty $(= $e:expr $(=> $m:expr)?)?; $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [] [] $(#[$($attr)*])* ($vis) static $kind $(($($opt),*))? $N : $T $(= $e $(=> $m)?)?;);
+    $crate::_ref_thread_local_internal!(@NEXT [$($M)* $N] $groups $($t)*);
+  };
+  (@GROUP {[$($attr:tt)*] ($($vis:tt)*) $G:ident} [$($M:ident)*]) => {
+    $($attr)*
+    #[allow(non_upper_case_globals)]
+    $($vis)* static $G: $crate::Group =
+      $crate::Group::new(::std::stringify!($G), &[$(&$M as &dyn $crate::ManagedStatic),*]);
+  };
+  (@ATTRS [$($attr:tt)*] [$($g:ident)*] #[group($($G:ident),+)] $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [$($attr)*] [$($g)* $($G)+] $($t)*);
+  };
+  (@ATTRS [$($attr:tt)*] [$($g:ident)*] #[$($a:tt)*] $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@ATTRS [$($attr)* #[$($a)*]] [$($g)*] $($t)*);
+  };
+  (@ATTRS [$($attr:tt)*] [$($g:ident)*] $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@STATIC $($attr)* [$($g)*] $($t)*);
+  };
+  (@STATIC $(#[$attr:meta])* [] ($($vis:tt)*) static managed group $N:ident { $($f:ident : $FT:ty = $e:expr),* $(,)? }) => {
+    #[allow(missing_copy_implementations)]
+    #[allow(non_camel_case_types)]
+    #[allow(dead_code)]
+    $(#[$attr])*
+    $($vis)* struct $N { $(pub $f: $crate::StructField<$FT>,)* __handle: $crate::StructHandle }
+    #[doc(hidden)]
+    $($vis)* static $N: $N = {
+      struct Values { $($f: $crate::FieldCell<$FT>,)* }
+      fn init_values() -> Values { Values { $($f: $crate::FieldCell::new($e),)* } }
+      fn is_borrowed(_values: &Values) -> bool { false $(|| _values.$f.is_borrowed())* }
+      fn get_structmanager() -> $crate::StructManager<Values> {
+        $crate::_create_structmanager_data!(STRUCT_SLOT, Values);
+        $crate::StructManager::new(&STRUCT_SLOT, init_values, is_borrowed).registered(&$N)
+      }
+      fn initialize() -> ::std::result::Result<(), ()> { $crate::StructThreadLocal::initialize(&get_structmanager()) }
+      fn destroy() -> ::std::result::Result<(), ()> { $crate::StructThreadLocal::destroy(&get_structmanager()) }
+      fn is_initialized() -> bool { $crate::StructThreadLocal::is_initialized(&get_structmanager()) }
+      fn is_any_borrowed() -> bool { get_structmanager().is_borrowed() }
+      $N {
+        $($f: $crate::StructField::new({
+          fn cell() -> *const $crate::FieldCell<$FT> { unsafe { &(*get_structmanager().get_initialized_values()).$f } }
+          cell
+        }),)*
+        __handle: $crate::StructHandle::new(initialize, destroy, is_initialized, is_any_borrowed),
+      }
+    };
+
+    impl $N {
+      fn is_borrowed(&self) -> bool { self.__handle.is_borrowed() }
+    }
+
+    impl $crate::StructThreadLocal for $N {
+      fn initialize(&self) -> ::std::result::Result<(), ()> { self.__handle.initialize() }
+      fn destroy(&self) -> ::std::result::Result<(), ()> { self.__handle.destroy() }
+      fn is_initialized(&self) -> bool { self.__handle.is_initialized() }
+    }
+    $crate::_ref_thread_local_internal!(@IMPL MEMBER, $N, StructThreadLocal);
+  };
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static managed $N:ident : $($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@STATIC $(#[$attr])* [$($g)*] ($($vis)*) static managed () $N : $($t)*);
+  };
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static managed (cow) $N:ident : $T:ty = $e:expr;) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL COW, $N : $T = $e, [$($g)*]);
+  };
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static managed (context $(, $opt:ident)*) $N:ident : $T:ty = |$ctx:pat| $e:expr $(=> $m:expr)?;) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL, $N : $T, ($ctx: &$crate::ThreadContext) $e, with_context, [$($opt)*], [$($m)?], [$($g)*]);
+  };
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static managed ($($opt:ident),*) $N:ident : $T:ty = $e:expr $(=> $m:expr)?;) => {
     $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
-    $crate::_ref_thread_local_internal!(@TAIL, $N : $T = $e);
-    $crate::ref_thread_local!($($t)*);
+    $crate::_ref_thread_local_internal!(@TAIL, $N : $T, () $e, new, [$($opt)*], [$($m)?], [$($g)*]);
   };
-  (@TAIL, $N:ident : $T:ty = $e:expr) => {
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static mirror $N:ident : $T:ty = from(&$G:expr);) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL MIRROR, $N : $T, $G, [$($g)*]);
+  };
+  (@STATIC $(#[$attr:meta])* [$($g:ident)*] ($($vis:tt)*) static once $N:ident : $T:ty;) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL ONCE, $N : $T, [$($g)*]);
+  };
+  (@STATIC $(#[$attr:meta])* [] ($($vis:tt)*) static scoped $N:ident : $T:ty;) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL SCOPED, $N : $T);
+  };
+  (@STATIC $(#[$attr:meta])* [] ($($vis:tt)*) static cell $N:ident : $T:ty = $e:expr;) => {
+    $crate::_ref_thread_local_internal!(@MAKE TY, $(#[$attr])*, ($($vis)*), $N);
+    $crate::_ref_thread_local_internal!(@TAIL CELL, $N : $T = $e);
+  };
+  (@TAIL, $N:ident : $T:ty, $params:tt $e:expr, $new:ident, [$($opt:ident)*], [$($m:expr)?], [$($g:ident)*]) => {
     impl $N {
       fn get_refmanager(&self) -> $crate::RefManager<$T> {
-        fn init_value() -> $T { $e }
+        fn init_value $params -> $T { $e }
+        $(fn merge_value(value: $T) { let merge = $m; merge(value) })?
+        $crate::_create_refmanager_data!(GUARDED_REF_MANAGER_DATA, $T);
+        let ref_manager = $crate::RefManager::$new(&GUARDED_REF_MANAGER_DATA, init_value);
+        $(let ref_manager = $crate::_ref_thread_local_internal!(@OPTION $opt, $N, ref_manager);)*
+        $($crate::_ref_thread_local_internal!(@IGNORE $m); let ref_manager = ref_manager.with_merge(merge_value);)?
+        ref_manager
+          .with_epoch($crate::EpochThreadLocal::epoch_counter(&$N))
+          .registered(&$N)
+      }
+    }
+
+    impl $crate::EpochThreadLocal<$T> for $N {
+      fn epoch_counter(&self) -> &'static ::std::sync::atomic::AtomicUsize {
+        static EPOCH: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
+        &EPOCH
+      }
+    }
+
+    $crate::_ref_thread_local_internal!(@IMPL REF, $N : $T, get_refmanager, [$($g)*]);
+    $crate::_ref_thread_local_internal!(@IMPL SHARE, $N : $T, get_refmanager);
+    $crate::_ref_thread_local_internal!(@IMPL PIN, $N : $T, get_refmanager);
+    $($crate::_ref_thread_local_internal!(@OPTION ITEMS $opt, $N : $T);)*
+    $($crate::_ref_thread_local_internal!(@IMPL MERGE, $N : $T, $m);)?
+  };
+  (@TAIL COW, $N:ident : $T:ty = $e:expr, [$($g:ident)*]) => {
+    impl $N {
+      fn get_cowmanager(&self) -> $crate::CowManager<$T> {
+        fn init_value() -> ::std::sync::Arc<$T> {
+          static SHARED_VALUE: ::std::sync::OnceLock<::std::sync::Arc<$T>> = ::std::sync::OnceLock::new();
+          SHARED_VALUE.get_or_init(|| ::std::sync::Arc::new($e)).clone()
+        }
+        $crate::_create_refmanager_data!(GUARDED_REF_MANAGER_DATA, ::std::sync::Arc<$T>);
+        $crate::CowManager::new($crate::RefManager::new(&GUARDED_REF_MANAGER_DATA, init_value).registered(&$N))
+      }
+    }
+
+    $crate::_ref_thread_local_internal!(@IMPL REF, $N : $T, get_cowmanager, [$($g)*]);
+  };
+  (@TAIL MIRROR, $N:ident : $T:ty, $G:expr, [$($g:ident)*]) => {
+    impl $N {
+      fn get_refmanager(&self) -> $crate::RefManager<$T> {
+        fn init_value() -> $T { $crate::GlobalCell::load(&$G) }
         $crate::_create_refmanager_data!(GUARDED_REF_MANAGER_DATA, $T);
         $crate::RefManager::new(&GUARDED_REF_MANAGER_DATA, init_value)
+          .with_epoch($crate::GlobalCell::version_counter(&$G))
+          .registered(&$N)
+      }
+    }
+
+    $crate::_ref_thread_local_internal!(@IMPL REF, $N : $T, get_refmanager, [$($g)*]);
+    $crate::_ref_thread_local_internal!(@IMPL SHARE, $N : $T, get_refmanager);
+    $crate::_ref_thread_local_internal!(@IMPL PIN, $N : $T, get_refmanager);
+  };
+  (@IMPL SHARE, $N:ident : $T:ty, $get_manager:ident) => {
+    impl $crate::ShareThreadLocal<$T> for $N {
+      fn share(&self) -> $crate::LocalRc<$T> { self.$get_manager().share() }
+      fn downgrade(&self) -> $crate::LocalWeak<$T> { self.$get_manager().downgrade() }
+    }
+  };
+  (@IMPL PIN, $N:ident : $T:ty, $get_manager:ident) => {
+    impl $crate::PinThreadLocal<$T> for $N {
+      fn try_borrow_pin<'_lifetime>(&self) -> ::std::result::Result<::std::pin::Pin<$crate::RefMut<'_lifetime, $T>>, $crate::BorrowMutError> { self.$get_manager().try_borrow_pin() }
+      fn as_mut_ptr(&self) -> *mut $T { self.$get_manager().as_mut_ptr() }
+    }
+  };
+  (@IMPL MERGE, $N:ident : $T:ty, $m:expr) => {
+    impl $crate::MergeThreadLocal<$T> for $N {
+      fn flush(&self) -> ::std::result::Result<(), ()> { self.get_refmanager().flush() }
+    }
+  };
+  (@IGNORE $($t:tt)*) => {};
+  (@OPTION shared, $N:ident, $ref_manager:ident) => {
+    $ref_manager.shared($crate::SharedThreadLocal::shared_registry(&$N))
+  };
+  (@OPTION reset_on_fork, $N:ident, $ref_manager:ident) => {
+    $ref_manager.reset_on_fork()
+  };
+  (@OPTION ITEMS reset_on_fork, $N:ident : $T:ty) => {};
+  (@OPTION context, $N:ident, $ref_manager:ident) => {
+    ::std::compile_error!("`context` must be the first option, and takes a `|ctx| EXPR` initializer")
+  };
+  (@OPTION ITEMS context, $N:ident : $T:ty) => {};
+  (@OPTION mailbox, $N:ident, $ref_manager:ident) => {
+    $ref_manager.with_mailbox($crate::MailboxThreadLocal::mailbox(&$N))
+  };
+  (@OPTION ITEMS mailbox, $N:ident : $T:ty) => {
+    impl $crate::MailboxThreadLocal<$T> for $N {
+      fn mailbox(&self) -> &'static $crate::Mailbox<$T> {
+        static MAILBOX: $crate::Mailbox<$T> = $crate::Mailbox::new();
+        &MAILBOX
       }
+      fn drain_mailbox(&self) -> usize { self.get_refmanager().drain_mailbox() }
     }
+  };
+  (@OPTION ITEMS shared, $N:ident : $T:ty) => {
+    impl $crate::SharedThreadLocal<$T> for $N {
+      fn shared_registry(&self) -> &'static $crate::SharedRegistry<$T> {
+        static SHARED_REGISTRY: $crate::SharedRegistry<$T> = $crate::SharedRegistry::new();
+        &SHARED_REGISTRY
+      }
+    }
+  };
+  (@TAIL ONCE, $N:ident : $T:ty, [$($g:ident)*]) => {
+    impl $N {
+      fn get_oncemanager(&self) -> $crate::OnceManager<$T> {
+        $crate::_create_refmanager_data!(GUARDED_REF_MANAGER_DATA, $T);
+        $crate::OnceManager::new(&GUARDED_REF_MANAGER_DATA, &$N)
+      }
+    }
+
+    $crate::_ref_thread_local_internal!(@IMPL REF, $N : $T, get_oncemanager, [$($g)*]);
+    $crate::_ref_thread_local_internal!(@IMPL SHARE, $N : $T, get_oncemanager);
+    $crate::_ref_thread_local_internal!(@IMPL PIN, $N : $T, get_oncemanager);
+  };
+  (@IMPL REF, $N:ident : $T:ty, $get_manager:ident, [$($g:ident)*]) => {
+    impl $crate::StaticInfo for $N {
+      type Value = $T;
+      const NAME: &'static str = ::std::stringify!($N);
+      const GROUPS: &'static [&'static str] = &[$(::std::stringify!($g)),*];
+      fn is_borrowed(&self) -> bool { self.$get_manager().is_borrowed() }
+    }
+    // every group must be declared by a `static group` in scope
+    $(const _: () = {
+      #[allow(dead_code)]
+      fn group() -> &'static $crate::Group { &$g }
+    };)*
 
     impl $crate::RefThreadLocal<$T> for $N {
-      fn initialize(&self) -> ::std::result::Result<(), ()> { self.get_refmanager().initialize() }
-      fn destroy(&self) -> ::std::result::Result<(), ()> { self.get_refmanager().destroy() }
-      fn is_initialized(&self) -> bool { self.get_refmanager().is_initialized() }
-      fn borrow<'_lifetime>(&self) -> $crate::Ref<'_lifetime, $T> { self.get_refmanager().borrow() }
-      fn borrow_mut<'_lifetime>(&self) -> $crate::RefMut<'_lifetime, $T> { self.get_refmanager().borrow_mut() }
-      fn try_borrow<'_lifetime>(&self) -> ::std::result::Result<$crate::Ref<'_lifetime, $T>, $crate::BorrowError> { self.get_refmanager().try_borrow() }
-      fn try_borrow_mut<'_lifetime>(&self) -> ::std::result::Result<$crate::RefMut<'_lifetime, $T>, $crate::BorrowMutError> { self.get_refmanager().try_borrow_mut() }
+      fn initialize(&self) -> ::std::result::Result<(), ()> { self.$get_manager().initialize() }
+      fn destroy(&self) -> ::std::result::Result<(), ()> { self.$get_manager().destroy() }
+      fn is_initialized(&self) -> bool { self.$get_manager().is_initialized() }
+      fn generation(&self) -> usize { self.$get_manager().generation() }
+      fn borrow<'_lifetime>(&self) -> $crate::Ref<'_lifetime, $T> { self.$get_manager().borrow() }
+      fn borrow_mut<'_lifetime>(&self) -> $crate::RefMut<'_lifetime, $T> { self.$get_manager().borrow_mut() }
+      fn try_borrow<'_lifetime>(&self) -> ::std::result::Result<$crate::Ref<'_lifetime, $T>, $crate::BorrowError> { self.$get_manager().try_borrow() }
+      fn try_borrow_mut<'_lifetime>(&self) -> ::std::result::Result<$crate::RefMut<'_lifetime, $T>, $crate::BorrowMutError> { self.$get_manager().try_borrow_mut() }
+    }
+
+    impl $crate::SetThreadLocal<$T> for $N {
+      fn set(&self, value: $T) -> ::std::result::Result<(), $T> { $crate::SetThreadLocal::set(&self.$get_manager(), value) }
+      fn take(&self) -> ::std::option::Option<$T> { $crate::SetThreadLocal::take(&self.$get_manager()) }
+    }
+
+    impl $crate::FreezeThreadLocal<$T> for $N {
+      fn freeze(&self) -> ::std::result::Result<(), ()> { self.$get_manager().freeze() }
+      unsafe fn unfreeze(&self) { self.$get_manager().unfreeze() }
+      fn is_frozen(&self) -> bool { self.$get_manager().is_frozen() }
+      fn with_frozen<R, F: FnOnce($crate::FrozenRef<'_, $T>) -> R>(&self, f: F) -> R { self.$get_manager().with_frozen(f) }
+    }
+  };
+  (@TAIL SCOPED, $N:ident : $T:ty) => {
+    impl $N {
+      fn get_scopedmanager(&self) -> $crate::ScopedManager<$T> {
+        $crate::_create_scopedmanager_data!(SCOPED_VALUE, $T);
+        $crate::ScopedManager::new(&SCOPED_VALUE)
+      }
+    }
+
+    impl $crate::ScopedThreadLocal<$T> for $N {
+      fn set<R, F: FnOnce() -> R>(&self, value: &$T, f: F) -> R { self.get_scopedmanager().set(value, f) }
+      fn with<R, F: FnOnce(&$T) -> R>(&self, f: F) -> ::std::result::Result<R, $crate::BorrowError> { self.get_scopedmanager().with(f) }
+      fn is_set(&self) -> bool { self.get_scopedmanager().is_set() }
+    }
+  };
+  (@TAIL CELL, $N:ident : $T:ty = $e:expr) => {
+    impl $N {
+      fn get_cellmanager(&self) -> $crate::CellManager<$T> {
+        fn init_value() -> $T { $e }
+        $crate::_create_refmanager_data!(GUARDED_REF_MANAGER_DATA, $T);
+        $crate::CellManager::new(&GUARDED_REF_MANAGER_DATA, init_value).registered(&$N)
+      }
+
+      // the value is only ever copied, so it is never borrowed
+      fn is_borrowed(&self) -> bool { false }
+    }
+
+    impl $crate::CellThreadLocal<$T> for $N {
+      fn initialize(&self) -> ::std::result::Result<(), ()> { self.get_cellmanager().initialize() }
+      fn destroy(&self) -> ::std::result::Result<(), ()> { self.get_cellmanager().destroy() }
+      fn is_initialized(&self) -> bool { self.get_cellmanager().is_initialized() }
+      fn get(&self) -> $T { self.get_cellmanager().get() }
+      fn set(&self, value: $T) { self.get_cellmanager().set(value) }
+      fn replace(&self, value: $T) -> $T { self.get_cellmanager().replace(value) }
+      fn update<F: FnOnce($T) -> $T>(&self, f: F) -> $T { self.get_cellmanager().update(f) }
+    }
+    $crate::_ref_thread_local_internal!(@IMPL MEMBER, $N, CellThreadLocal);
+  };
+  // `static cell` and `static managed group` variables cannot be frozen nor be members of a
+  // group, but are still destroyed by `shutdown()`.
+  (@IMPL MEMBER, $N:ident, $Trait:ident) => {
+    impl $crate::ManagedStatic for $N {
+      fn static_type_id(&self) -> ::std::any::TypeId { ::std::any::TypeId::of::<$N>() }
+      fn static_name(&self) -> &'static str { ::std::stringify!($N) }
+      fn static_groups(&self) -> &'static [&'static str] { &[] }
+      fn static_status(&self) -> $crate::StaticStatus {
+        let initialized = $crate::$Trait::is_initialized(self);
+        $crate::StaticStatus {
+          name: ::std::stringify!($N),
+          initialized,
+          frozen: false,
+          borrowed: initialized && self.is_borrowed(),
+        }
+      }
+      fn initialize_static(&self) -> ::std::result::Result<(), ()> { $crate::$Trait::initialize(self) }
+      fn destroy_static(&self) -> ::std::result::Result<(), ()> { $crate::$Trait::destroy(self) }
     }
   };
   (@MAKE TY, $(#[$attr:meta])*, ($($vis:tt)*), $N:ident) => {
@@ -137,19 +1025,63 @@ macro_rules! _ref_thread_local_internal {
     #[doc(hidden)]
     $($vis)* static $N: $N = $N { _private_field: () };
   };
-  () => ()
 }
 
 #[macro_export(local_inner_macros)]
-macro_rules! ref_thread_local {
-  ($(#[$attr:meta])* static managed $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
-    $crate::_ref_thread_local_internal!($(#[$attr])* () static $N : $T = $e; $($t)*);
+#[doc(hidden)]
+macro_rules! _task_local_managed_internal {
+  ([$($attr:tt)*] ($($vis:tt)*) $N:ident : $T:ty; $($t:tt)*) => {
+    $crate::ref_thread_local!($($attr)* $($vis)* static once $N : $T;);
+    impl $crate::TaskLocalThreadLocal<$T> for $N {}
+    $crate::task_local_managed!($($t)*);
+  };
+  ([$($attr:tt)*] ($($vis:tt)*) $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
+    $crate::ref_thread_local!($($attr)* $($vis)* static managed $N : $T = $e;);
+    impl $crate::TaskLocalThreadLocal<$T> for $N {}
+    $crate::task_local_managed!($($t)*);
+  };
+}
+
+/// Declares variables whose values follow futures, see `TaskLocalThreadLocal`.
+///
+/// `static NAME: TYPE;` is declared like a `static once` of `ref_thread_local!`, which is
+/// only set inside `NAME.scope(value, future)`, and `static NAME: TYPE = EXPR;` like a
+/// `static managed`, which falls back to its own initialized value outside of a scope.
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate ref_thread_local;
+/// use ref_thread_local::{RefThreadLocal, TaskLocalThreadLocal};
+///
+/// task_local_managed! {
+///     static REQUEST_ID: u64;
+/// }
+///
+/// # fn main() {
+/// let task = REQUEST_ID.scope(
+///     42,
+///     std::future::poll_fn(|_| std::task::Poll::Ready(*REQUEST_ID.borrow())),
+/// );
+/// # let _ = task;
+/// # }
+/// ```
+#[macro_export(local_inner_macros)]
+macro_rules! task_local_managed {
+  ($(#[$($attr:tt)*])* static $($t:tt)*) => {
+    $crate::_task_local_managed_internal!([$(#[$($attr)*])*] () $($t)*);
   };
-  ($(#[$attr:meta])* pub static managed $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
-    $crate::_ref_thread_local_internal!($(#[$attr])* (pub) static $N : $T = $e; $($t)*);
+  ($(#[$($attr:tt)*])* pub static $($t:tt)*) => {
+    $crate::_task_local_managed_internal!([$(#[$($attr)*])*] (pub) $($t)*);
   };
-  ($(#[$attr:meta])* pub ($($vis:tt)+) static managed $N:ident : $T:ty = $e:expr; $($t:tt)*) => {
-    $crate::_ref_thread_local_internal!($(#[$attr])* (pub ($($vis)+)) static $N : $T = $e; $($t)*);
+  ($(#[$($attr:tt)*])* pub ($($vis:tt)+) static $($t:tt)*) => {
+    $crate::_task_local_managed_internal!([$(#[$($attr)*])*] (pub ($($vis)+)) $($t)*);
   };
   () => ()
 }
+
+#[macro_export(local_inner_macros)]
+macro_rules! ref_thread_local {
+  ($($t:tt)*) => {
+    $crate::_ref_thread_local_internal!(@NEXT [] [] $($t)*);
+  };
+}
@@ -0,0 +1,160 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use refmanager::{
+    BorrowError, BorrowMutError, Ref, RefManagerDataGuard, RefManagerInnerData, RefMut,
+};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::thread::LocalKey;
+
+/// A counted handle to a thread's value of a static, obtained with
+/// `ShareThreadLocal::share()`.
+///
+/// The value stays alive as long as a handle to it exists, even if the static is destroyed
+/// in the meantime: `destroy()` then detaches the slot from the value, which is dropped (or
+/// merged) when its last handle is dropped. The value is borrowed through the handle with
+/// the same borrow count as through the static, so `destroy()` also succeeds while it is
+/// borrowed, and the borrows stay valid.
+///
+/// `LocalRc` is neither `Send` nor `Sync`.
+pub struct LocalRc<T> {
+    ptr_inner_data: *mut RefManagerInnerData<T>,
+}
+
+impl<T> LocalRc<T> {
+    // `ptr_inner_data` must point to the value of an initialized slot.
+    pub(crate) unsafe fn new(ptr_inner_data: *mut RefManagerInnerData<T>) -> Self {
+        let shares = &(*ptr_inner_data).shares;
+        shares.set(shares.get() + 1);
+        LocalRc { ptr_inner_data }
+    }
+
+    fn inner_data(&self) -> &RefManagerInnerData<T> {
+        unsafe { &*self.ptr_inner_data }
+    }
+
+    /// Returns `true` if the static this value came from has been destroyed since.
+    pub fn is_detached(&self) -> bool {
+        self.inner_data().detached.get()
+    }
+
+    /// Returns `true` if both handles share the same value.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        std::ptr::eq(this.ptr_inner_data, other.ptr_inner_data)
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.inner_data().try_borrow()
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        unsafe { RefManagerInnerData::try_borrow_mut(self.ptr_inner_data) }
+    }
+}
+
+impl<T> Clone for LocalRc<T> {
+    fn clone(&self) -> Self {
+        unsafe { LocalRc::new(self.ptr_inner_data) }
+    }
+}
+
+impl<T> Drop for LocalRc<T> {
+    fn drop(&mut self) {
+        let inner_data = self.inner_data();
+        inner_data.shares.set(inner_data.shares.get() - 1);
+        if inner_data.shares.get() == 0 && inner_data.detached.get() {
+            // a reference obtained from the static before it was destroyed may still be
+            // alive, in which case the value is leaked rather than freed under it
+            if inner_data.borrow_count.get() == 0 && !inner_data.is_frozen() {
+                RefManagerInnerData::release(self.ptr_inner_data);
+            }
+        }
+    }
+}
+
+impl<T: Debug> Debug for LocalRc<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.try_borrow() {
+            Ok(value) => f.debug_tuple("LocalRc").field(&*value).finish(),
+            Err(_) => f.write_str("LocalRc(<borrowed>)"),
+        }
+    }
+}
+
+/// A handle to a thread's value of a static that does not keep it alive, obtained with
+/// `ShareThreadLocal::downgrade()`.
+///
+/// The handle remembers the generation of the slot it was created from, and only reaches
+/// the value as long as the slot has neither been destroyed nor set again since, even if
+/// the new value happens to be equal.
+///
+/// `LocalWeak` is neither `Send` nor `Sync`.
+pub struct LocalWeak<T: 'static> {
+    local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+    generation: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> LocalWeak<T> {
+    pub(crate) fn new(
+        local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+        generation: usize,
+    ) -> Self {
+        LocalWeak {
+            local_key,
+            generation,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns the generation of the value this handle refers to.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Returns `true` if the value this handle refers to is still in place.
+    pub fn is_alive(&self) -> bool {
+        self.local_key
+            .try_with(|guard| guard.is_initialized() && guard.generation() == self.generation)
+            .unwrap_or(false)
+    }
+
+    /// Borrows the value if it is still in place, or returns `None` once it has been
+    /// destroyed, taken or replaced, or when its thread is exiting. Also returns `None`
+    /// while the value is mutably borrowed, in which case `is_alive()` still returns `true`.
+    pub fn upgrade(&self) -> Option<Ref<'_, T>> {
+        if !self.is_alive() {
+            return None;
+        }
+        self.local_key.with(|guard| guard.try_borrow()).ok()
+    }
+}
+
+impl<T> Clone for LocalWeak<T> {
+    fn clone(&self) -> Self {
+        LocalWeak::new(self.local_key, self.generation)
+    }
+}
+
+impl<T> Debug for LocalWeak<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("LocalWeak")
+            .field("generation", &self.generation)
+            .field("alive", &self.is_alive())
+            .finish()
+    }
+}
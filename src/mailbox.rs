@@ -0,0 +1,183 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+type Job<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// The per-thread queues of work posted to a `managed(mailbox)` static.
+pub struct Mailbox<T> {
+    state: Mutex<MailboxState<T>>,
+    // lets borrows skip the lock while nothing is queued for any thread
+    pending: AtomicUsize,
+}
+
+struct MailboxState<T> {
+    jobs: Vec<(ThreadId, Job<T>)>,
+    // the live threads that hold a value, which jobs can be posted to
+    owners: Vec<ThreadId>,
+}
+
+impl<T: 'static> Mailbox<T> {
+    pub const fn new() -> Self {
+        Mailbox {
+            state: Mutex::new(MailboxState {
+                jobs: Vec::new(),
+                owners: Vec::new(),
+            }),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MailboxState<T>> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Queues `job` to be run by the thread `thread_id` on its value.
+    ///
+    /// The returned handle can be used to wait for the job to be run. It can be dropped as
+    /// well. If `thread_id` has no value, because it has not initialized it, has destroyed
+    /// it, or has exited, the job is dropped right away.
+    pub fn post(&self, thread_id: ThreadId, job: Job<T>) -> Completion {
+        let (sender, receiver) = mpsc::channel();
+        let job: Job<T> = Box::new(move |value| {
+            job(value);
+            let _ = sender.send(());
+        });
+        let mut state = self.lock();
+        if state.owners.contains(&thread_id) {
+            state.jobs.push((thread_id, job));
+            self.pending.fetch_add(1, Ordering::Release);
+        } else {
+            // dropped outside of the lock, as the job may own anything
+            drop(state);
+            drop(job);
+        }
+        Completion { receiver }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire) != 0
+    }
+
+    // Removes the jobs queued for the current thread, in posting order.
+    pub(crate) fn take(&self) -> Vec<Job<T>> {
+        if !self.has_pending() {
+            return Vec::new();
+        }
+        self.take_for(thread::current().id())
+    }
+
+    fn take_for(&self, thread_id: ThreadId) -> Vec<Job<T>> {
+        let mut state = self.lock();
+        let jobs = &mut state.jobs;
+        let mut taken = Vec::new();
+        let mut i = 0;
+        while i < jobs.len() {
+            if jobs[i].0 == thread_id {
+                taken.push(jobs.remove(i).1);
+            } else {
+                i += 1;
+            }
+        }
+        self.pending.fetch_sub(taken.len(), Ordering::Release);
+        taken
+    }
+
+    // Called when the current thread destroys its value, or moves it out: the mail left for
+    // it is dropped, and no more can be posted to it until it has a value again.
+    pub(crate) fn disown(&self) {
+        self.discard(thread::current().id());
+    }
+}
+
+impl<T> Debug for Mailbox<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("Mailbox")
+            .field("pending", &self.pending.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl<T: 'static> Default for Mailbox<T> {
+    fn default() -> Self {
+        Mailbox::new()
+    }
+}
+
+/// Reports when a job posted to a mailbox has been run.
+#[derive(Debug)]
+pub struct Completion {
+    receiver: Receiver<()>,
+}
+
+impl Completion {
+    /// Blocks until the job has been run. Fails if the job will never run to completion:
+    /// because it panicked, or because its thread could not receive it or exited first.
+    pub fn wait(self) -> Result<(), RecvError> {
+        self.receiver.recv()
+    }
+}
+
+trait DiscardMail {
+    fn discard(&self, thread_id: ThreadId);
+}
+
+impl<T: 'static> DiscardMail for Mailbox<T> {
+    fn discard(&self, thread_id: ThreadId) {
+        self.lock().owners.retain(|&owner| owner != thread_id);
+        // dropped outside of the lock, as the jobs may own anything
+        drop(self.take_for(thread_id));
+    }
+}
+
+struct OwnedMailboxes {
+    thread_id: ThreadId,
+    mailboxes: RefCell<Vec<&'static dyn DiscardMail>>,
+}
+
+thread_local! {
+    static OWNED_MAILBOXES: OwnedMailboxes = OwnedMailboxes {
+        thread_id: thread::current().id(),
+        mailboxes: RefCell::new(Vec::new()),
+    };
+}
+
+impl Drop for OwnedMailboxes {
+    fn drop(&mut self) {
+        for mailbox in self.mailboxes.borrow().iter() {
+            mailbox.discard(self.thread_id);
+        }
+    }
+}
+
+// Called when the current thread initializes a value with `mailbox`, so that jobs can be
+// posted to it until it destroys the value or exits, when the mail left for it is discarded.
+pub(crate) fn register_owner<T: 'static>(typed_mailbox: &'static Mailbox<T>) {
+    let mailbox = typed_mailbox as &'static dyn DiscardMail;
+    let _ = OWNED_MAILBOXES.try_with(|owned| {
+        let mut mailboxes = owned.mailboxes.borrow_mut();
+        let known = mailboxes.iter().any(|&other| {
+            std::ptr::eq(
+                other as *const dyn DiscardMail as *const u8,
+                mailbox as *const dyn DiscardMail as *const u8,
+            )
+        });
+        if !known {
+            mailboxes.push(mailbox);
+        }
+        let mut state = typed_mailbox.lock();
+        if !state.owners.contains(&owned.thread_id) {
+            state.owners.push(owned.thread_id);
+        }
+    });
+}
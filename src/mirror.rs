@@ -0,0 +1,84 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// A process-wide value that `static mirror` variables copy into every thread.
+///
+/// The value is built by `init` on first use, and replaced with `publish()`. Each thread's
+/// mirror notices the new version on its next access and picks up the new `Arc`, so that
+/// readers only take the lock when the value has changed.
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate ref_thread_local;
+/// use ref_thread_local::{GlobalCell, RefThreadLocal};
+/// use std::sync::Arc;
+///
+/// static GLOBAL_LIMIT: GlobalCell<u32> = GlobalCell::new(|| 10);
+///
+/// ref_thread_local! {
+///     static mirror LIMIT: Arc<u32> = from(&GLOBAL_LIMIT);
+/// }
+///
+/// # fn main() {
+/// assert_eq!(**LIMIT.borrow(), 10);
+/// GLOBAL_LIMIT.publish(20);
+/// assert_eq!(**LIMIT.borrow(), 20);
+/// # }
+/// ```
+pub struct GlobalCell<T> {
+    init: fn() -> T,
+    value: RwLock<Option<Arc<T>>>,
+    version: AtomicUsize,
+}
+
+impl<T> GlobalCell<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        GlobalCell {
+            init,
+            value: RwLock::new(None),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the current value, building it first if needed.
+    pub fn load(&self) -> Arc<T> {
+        if let Some(ref value) = *self.value.read().unwrap_or_else(|err| err.into_inner()) {
+            return value.clone();
+        }
+        let mut value = self.value.write().unwrap_or_else(|err| err.into_inner());
+        value.get_or_insert_with(|| Arc::new((self.init)())).clone()
+    }
+
+    /// Replaces the value. Every thread's mirror switches to it on its next access, unless
+    /// it is borrowed at that point.
+    pub fn publish(&self, value: T) {
+        *self.value.write().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(value));
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Returns the number of times a value has been published.
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Acquire)
+    }
+
+    #[doc(hidden)]
+    pub fn version_counter(&self) -> &AtomicUsize {
+        &self.version
+    }
+}
+
+impl<T> Debug for GlobalCell<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("GlobalCell")
+            .field("version", &self.version())
+            .finish()
+    }
+}
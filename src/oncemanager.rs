@@ -0,0 +1,143 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{RefThreadLocal, SetThreadLocal};
+use group::{self, ManagedStatic};
+use localrc::{LocalRc, LocalWeak};
+use refmanager::{BorrowError, BorrowMutError, FrozenRef, Ref, RefManagerDataGuard, RefMut};
+use std::pin::Pin;
+use std::thread::LocalKey;
+
+// A `static once` has no initializer: its slot stays empty until the thread calls `set()`.
+#[derive(Debug)]
+pub struct OnceManager<T: 'static> {
+    local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+    member: &'static dyn ManagedStatic,
+}
+
+impl<T> OnceManager<T> {
+    pub fn new(
+        local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+        member: &'static dyn ManagedStatic,
+    ) -> Self {
+        OnceManager { local_key, member }
+    }
+}
+
+impl<T> OnceManager<T> {
+    pub fn share(&self) -> LocalRc<T> {
+        match self.local_key.with(|guard| guard.share()) {
+            Some(local_rc) => local_rc,
+            None => panic!("`{}` is not set on this thread", self.member.static_name()),
+        }
+    }
+
+    pub fn try_borrow_pin<'a>(&self) -> Result<Pin<RefMut<'a, T>>, BorrowMutError> {
+        self.local_key.with(|guard| guard.try_borrow_pin())
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut T {
+        let ptr = self.local_key.with(|guard| guard.as_ptr());
+        if ptr.is_null() {
+            panic!("`{}` is not set on this thread", self.member.static_name());
+        }
+        ptr
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn freeze(&self) -> Result<(), ()> {
+        self.local_key.with(|guard| guard.freeze())
+    }
+
+    pub unsafe fn unfreeze(&self) {
+        self.local_key.with(|guard| guard.unfreeze())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.local_key.with(|guard| guard.is_frozen())
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        self.local_key.with(|guard| guard.is_borrowed())
+    }
+
+    pub fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R {
+        match self.local_key.with(|guard| guard.with_frozen(f)) {
+            Ok(result) => result,
+            Err(ref err) if err.is_unset() => {
+                panic!("`{}` is not set on this thread", self.member.static_name())
+            }
+            Err(_) => panic!("already mutably borrowed"),
+        }
+    }
+
+    pub fn downgrade(&self) -> LocalWeak<T> {
+        if !self.is_initialized() {
+            panic!("`{}` is not set on this thread", self.member.static_name());
+        }
+        LocalWeak::new(self.local_key, self.generation())
+    }
+}
+
+impl<T> RefThreadLocal<T> for OnceManager<T> {
+    fn initialize(&self) -> Result<(), ()> {
+        // there is nothing to initialize the value from
+        Err(())
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        self.local_key.with(|guard| guard.destroy())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.local_key.with(|guard| guard.is_initialized())
+    }
+
+    fn generation(&self) -> usize {
+        self.local_key.with(|guard| guard.generation())
+    }
+
+    fn borrow<'a>(&self) -> Ref<'a, T> {
+        match self.try_borrow() {
+            Ok(value) => value,
+            Err(ref err) if err.is_unset() => {
+                panic!("`{}` is not set on this thread", self.member.static_name())
+            }
+            Err(_) => panic!("already mutably borrowed"),
+        }
+    }
+
+    fn borrow_mut<'a>(&self) -> RefMut<'a, T> {
+        match self.try_borrow_mut() {
+            Ok(value) => value,
+            Err(ref err) if err.is_unset() => {
+                panic!("`{}` is not set on this thread", self.member.static_name())
+            }
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        self.local_key.with(|guard| guard.try_borrow())
+    }
+
+    fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        self.local_key.with(|guard| guard.try_borrow_mut())
+    }
+}
+
+impl<T> SetThreadLocal<T> for OnceManager<T> {
+    fn set(&self, value: T) -> Result<(), T> {
+        self.local_key.with(|guard| guard.set(value))?;
+        group::register(self.member);
+        Ok(())
+    }
+
+    fn take(&self) -> Option<T> {
+        self.local_key.with(|guard| guard.take())
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use refmanager::{BorrowError, BorrowMutError, FrozenRef, Ref, RefManagerDataGuard, RefMut};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread::{self, ThreadId};
+
+/// A thread-local value that is not tied to a `static` item.
+///
+/// Each thread that uses a `PerThread<T>` gets its own lazily initialized `T`, borrowed
+/// through the same methods as the variables of `ref_thread_local!`, except that `Ref`s and
+/// `RefMut`s cannot outlive the `PerThread`. A thread's value is dropped when that thread
+/// exits or when the `PerThread` is dropped, whichever comes first; in the latter case it is
+/// dropped on the thread dropping the `PerThread`, which is why `T` has to be `Send`.
+///
+/// ```rust
+/// use ref_thread_local::PerThread;
+/// use std::sync::Arc;
+///
+/// let buffers = Arc::new(PerThread::new(Vec::<u8>::new));
+/// let other = buffers.clone();
+/// std::thread::spawn(move || other.borrow_mut().push(1)).join().unwrap();
+/// assert!(buffers.borrow().is_empty());
+/// ```
+pub struct PerThread<T: Send + 'static> {
+    slots: Arc<PerThreadSlots<T>>,
+    init_func: Box<dyn Fn() -> T + Send + Sync>,
+}
+
+struct PerThreadSlots<T> {
+    guards: Mutex<HashMap<ThreadId, Box<PerThreadGuard<T>>>>,
+}
+
+// Each guard is only ever used by the thread it belongs to, except when it is dropped
+// together with the `PerThread` that owns it.
+struct PerThreadGuard<T>(RefManagerDataGuard<T>);
+
+unsafe impl<T: Send> Send for PerThreadGuard<T> {}
+
+trait ThreadExitHook {
+    fn thread_exited(&self, thread_id: ThreadId);
+}
+
+struct ThreadExitHooks {
+    thread_id: ThreadId,
+    hooks: RefCell<Vec<Weak<dyn ThreadExitHook>>>,
+}
+
+thread_local! {
+    static THREAD_EXIT_HOOKS: ThreadExitHooks = ThreadExitHooks {
+        thread_id: thread::current().id(),
+        hooks: RefCell::new(Vec::new()),
+    };
+}
+
+impl<T: Send> PerThread<T> {
+    pub fn new<F>(init_func: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        PerThread {
+            slots: Arc::new(PerThreadSlots {
+                guards: Mutex::new(HashMap::new()),
+            }),
+            init_func: Box::new(init_func),
+        }
+    }
+
+    // Returns the current thread's guard, creating it on first use.
+    fn get_guard(&self) -> &RefManagerDataGuard<T> {
+        let thread_id = thread::current().id();
+        let (ptr_guard, inserted) = {
+            let mut guards = self.slots.lock();
+            let mut inserted = false;
+            let guard = guards.entry(thread_id).or_insert_with(|| {
+                inserted = true;
+                Box::new(PerThreadGuard(RefManagerDataGuard::INIT_SELF))
+            });
+            (&guard.0 as *const RefManagerDataGuard<T>, inserted)
+        };
+        if inserted {
+            let slots = Arc::downgrade(&self.slots) as Weak<dyn ThreadExitHook>;
+            // if the thread is already tearing down its thread-locals, the value will be
+            // dropped along with the `PerThread` instead
+            let _ = THREAD_EXIT_HOOKS.try_with(|exit_hooks| {
+                let mut hooks = exit_hooks.hooks.borrow_mut();
+                hooks.retain(|hook| hook.strong_count() > 0);
+                hooks.push(slots);
+            });
+        }
+        // the box is only removed by this thread exiting or by dropping `self`
+        unsafe { &*ptr_guard }
+    }
+
+    fn get_initialized_guard(&self) -> &RefManagerDataGuard<T> {
+        let guard = self.get_guard();
+        if !guard.is_initialized() {
+            let _ = guard.set((self.init_func)());
+        }
+        guard
+    }
+}
+
+impl<T> PerThreadSlots<T> {
+    fn lock(&self) -> MutexGuard<'_, HashMap<ThreadId, Box<PerThreadGuard<T>>>> {
+        self.guards.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<T: Send> ThreadExitHook for PerThreadSlots<T> {
+    fn thread_exited(&self, thread_id: ThreadId) {
+        let guard = self.lock().remove(&thread_id);
+        // dropped outside of the lock, as the value may use other `PerThread`s
+        drop(guard);
+    }
+}
+
+impl Drop for ThreadExitHooks {
+    fn drop(&mut self) {
+        for hook in self.hooks.borrow_mut().drain(..) {
+            if let Some(hook) = hook.upgrade() {
+                hook.thread_exited(self.thread_id);
+            }
+        }
+    }
+}
+
+impl<T: Send> PerThread<T> {
+    /// Initializes the current thread's value. Fails if it is already initialized.
+    #[allow(clippy::result_unit_err)]
+    pub fn initialize(&self) -> Result<(), ()> {
+        let guard = self.get_guard();
+        if guard.is_initialized() {
+            Err(())
+        } else {
+            guard.set((self.init_func)()).map_err(|_| ())
+        }
+    }
+
+    /// Drops the current thread's value, like `RefThreadLocal::destroy()`.
+    #[allow(clippy::result_unit_err)]
+    pub fn destroy(&self) -> Result<(), ()> {
+        self.get_guard().destroy()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.get_guard().is_initialized()
+    }
+
+    /// Stores `value` if the current thread's value is not initialized, or hands it back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.get_guard().set(value)
+    }
+
+    /// Moves the current thread's value out, like `SetThreadLocal::take()`.
+    pub fn take(&self) -> Option<T> {
+        self.get_guard().take()
+    }
+
+    /// Returns the generation of the current thread's slot, like
+    /// `RefThreadLocal::generation()`.
+    pub fn generation(&self) -> usize {
+        self.get_guard().generation()
+    }
+
+    /// Makes the current thread's value read-only until `unfreeze()` is called or the
+    /// `PerThread` is dropped, like `FreezeThreadLocal::freeze()`. If the thread exits first,
+    /// the value is leaked.
+    #[allow(clippy::result_unit_err)]
+    pub fn freeze(&self) -> Result<(), ()> {
+        self.get_initialized_guard().freeze()
+    }
+
+    /// Makes the current thread's value writable again after `freeze()`. Unlike
+    /// `FreezeThreadLocal::unfreeze()` this is safe, since references are bound to `self`.
+    pub fn unfreeze(&mut self) {
+        unsafe { self.get_guard().unfreeze() }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.get_guard().is_frozen()
+    }
+
+    /// Calls `f` with the current thread's value, which is read-only until `f` returns, like
+    /// `FreezeThreadLocal::with_frozen()`.
+    pub fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R {
+        self.get_initialized_guard()
+            .with_frozen(f)
+            .expect("already mutably borrowed")
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.get_initialized_guard().try_borrow()
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        self.get_initialized_guard().try_borrow_mut()
+    }
+}
+
+impl<T: Send> Drop for PerThread<T> {
+    fn drop(&mut self) {
+        let guards: Vec<_> = self.slots.lock().drain().map(|(_, guard)| guard).collect();
+        for guard in guards {
+            // references are bound to `self`, so none is left, even to a frozen value
+            unsafe { guard.0.unfreeze() };
+            let _ = guard.0.destroy();
+        }
+    }
+}
+
+impl<T: Send> Debug for PerThread<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("PerThread")
+            .field("threads", &self.slots.lock().len())
+            .finish()
+    }
+}
@@ -5,31 +5,154 @@
 // or distributed except according to those terms.
 
 extern crate std;
-use super::RefThreadLocal;
-use std::cell::Cell;
+use super::ThreadContext;
+use super::{RefThreadLocal, SetThreadLocal};
+use fork;
+use group::{self, ManagedStatic};
+use localrc::{LocalRc, LocalWeak};
+use mailbox::{self, Mailbox};
+use shared::{SharedCount, SharedRegistry};
+use std::cell::{Cell, UnsafeCell};
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
 use std::ptr::{null, null_mut};
-use std::thread::LocalKey;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, LocalKey};
 
-struct RefManagerInnerData<T> {
-    borrow_count: Cell<isize>,
-    value: T,
+pub(crate) struct RefManagerInnerData<T> {
+    pub(crate) borrow_count: BorrowCount,
+    frozen: Cell<bool>,
+    // the number of running `with_frozen()` calls, during which the value stays in place
+    frozen_scopes: Cell<usize>,
+    // set by `borrow_pin()`, after which the value must not be moved until it is dropped
+    pinned: Cell<bool>,
+    // only allocated for the values of statics declared `managed(shared)` or with a merge
+    // function, so that the others do not pay for them
+    options: Option<Box<InnerOptions<T>>>,
+    epoch: usize,
+    // the number of `LocalRc`s, which keep the value alive once the slot is destroyed
+    pub(crate) shares: Cell<usize>,
+    pub(crate) detached: Cell<bool>,
+    // references to the other fields stay valid while the value is mutably borrowed
+    pub(crate) value: UnsafeCell<T>,
+}
+
+struct InnerOptions<T> {
+    registry: *const SharedRegistry<T>,
+    merge_func: Option<fn(T)>,
+}
+
+impl<T> RefManagerInnerData<T> {
+    fn registry(&self) -> Option<&SharedRegistry<T>> {
+        let options = self.options.as_ref()?;
+        unsafe { options.registry.as_ref() }
+    }
+
+    fn merge_func(&self) -> Option<fn(T)> {
+        self.options.as_ref().and_then(|options| options.merge_func)
+    }
+
+    // The returned reference must not outlive the allocation, which the slot or the
+    // `LocalRc`s sharing it keep alive.
+    pub(crate) fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        let value = unsafe { &*self.value.get() };
+        if self.frozen.get() {
+            return Ok(Ref {
+                borrow_count: None,
+                value,
+            });
+        }
+        let borrow_count = unsafe { &*(&self.borrow_count as *const BorrowCount) };
+        if !borrow_count.try_borrow() {
+            return Err(BorrowError {
+                kind: BorrowErrorKind::MutablyBorrowed,
+            });
+        }
+        Ok(Ref {
+            borrow_count: Some(borrow_count),
+            value,
+        })
+    }
+
+    // Takes a pointer, as other references to the value may be alive until the borrow count
+    // has been checked.
+    pub(crate) unsafe fn try_borrow_mut<'a>(
+        ptr_inner_data: *mut Self,
+    ) -> Result<RefMut<'a, T>, BorrowMutError> {
+        if (*ptr_inner_data).pinned.get() && !(*ptr_inner_data).frozen.get() {
+            return Err(BorrowMutError {
+                kind: BorrowErrorKind::Pinned,
+            });
+        }
+        Self::try_borrow_unpinned(ptr_inner_data)
+    }
+
+    // A pinned value is only handed out as `Pin<RefMut<T>>` from then on, and cannot be
+    // moved out of its allocation by `take()` or a merge function.
+    pub(crate) unsafe fn try_borrow_pin<'a>(
+        ptr_inner_data: *mut Self,
+    ) -> Result<Pin<RefMut<'a, T>>, BorrowMutError> {
+        if (*ptr_inner_data).merge_func().is_some() {
+            return Err(BorrowMutError {
+                kind: BorrowErrorKind::Merged,
+            });
+        }
+        let value = Self::try_borrow_unpinned(ptr_inner_data)?;
+        (*ptr_inner_data).pinned.set(true);
+        Ok(Pin::new_unchecked(value))
+    }
+
+    unsafe fn try_borrow_unpinned<'a>(
+        ptr_inner_data: *mut Self,
+    ) -> Result<RefMut<'a, T>, BorrowMutError> {
+        if (*ptr_inner_data).is_frozen() {
+            return Err(BorrowMutError {
+                kind: BorrowErrorKind::Frozen,
+            });
+        }
+        let borrow_count = &*std::ptr::addr_of!((*ptr_inner_data).borrow_count);
+        if !borrow_count.try_borrow_mut() {
+            return Err(BorrowMutError {
+                kind: BorrowErrorKind::Borrowed,
+            });
+        }
+        Ok(RefMut {
+            borrow_count,
+            value: &mut *(*ptr_inner_data).value.get(),
+        })
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.frozen.get() || self.frozen_scopes.get() != 0
+    }
+
+    // Drops or merges the value of a slot that has been removed.
+    pub(crate) fn release(ptr_inner_data: *mut Self) {
+        let inner_data = unsafe { Box::from_raw(ptr_inner_data) };
+        match inner_data.merge_func() {
+            Some(merge_func) => merge_func(inner_data.value.into_inner()),
+            None => drop(inner_data),
+        }
+    }
+}
+
+// Values of `managed(shared)` statics can be visited by other threads, so their borrow count
+// has to be atomic, and shared with the visitors.
+pub(crate) enum BorrowCount {
+    Local(Cell<isize>),
+    Shared(Arc<SharedCount>),
 }
 
 pub struct RefManagerPeekData<T> {
     ptr_inner_data: *mut RefManagerInnerData<T>,
-    ptr_borrow_count: *const Cell<isize>,
     ptr_value: *mut T,
 }
 
 impl<T> Clone for RefManagerPeekData<T> {
     fn clone(&self) -> Self {
-        return Self {
-            ptr_inner_data: self.ptr_inner_data,
-            ptr_borrow_count: self.ptr_borrow_count,
-            ptr_value: self.ptr_value,
-        };
+        *self
     }
 }
 
@@ -37,32 +160,85 @@ impl<T> Copy for RefManagerPeekData<T> {}
 
 pub struct RefManagerDataGuard<T> {
     peek_data: Cell<RefManagerPeekData<T>>,
+    // incremented whenever a value is installed or removed
+    generation: Cell<usize>,
 }
 
 pub struct Ref<'a, T: ?Sized + 'a> {
-    borrow_count: &'a Cell<isize>,
+    borrow_count: Option<&'a BorrowCount>,
     value: &'a T,
 }
 
 pub struct RefMut<'a, T: ?Sized + 'a> {
-    borrow_count: &'a Cell<isize>,
+    borrow_count: &'a BorrowCount,
     value: &'a mut T,
 }
 
+/// The reference handed to the closure of `FreezeThreadLocal::with_frozen()`.
+///
+/// It does not touch the borrow count and can be copied freely, but cannot outlive the
+/// closure, after which the value is writable again.
+pub struct FrozenRef<'a, T: ?Sized + 'a> {
+    value: &'a T,
+}
+
+/// Manages the thread-local value stored behind `local_key`.
+///
+/// This is what `ref_thread_local!` builds on, and it can be used directly to build other
+/// thread-local facades, for example with an initializer that captures runtime state:
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate ref_thread_local;
+/// use ref_thread_local::{RefManager, RefManagerDataGuard, RefThreadLocal};
+///
+/// thread_local! {
+///     static DATA: RefManagerDataGuard<String> = const { RefManagerDataGuard::INIT_SELF };
+/// }
+///
+/// # fn main() {
+/// let prefix = std::env::args().count().to_string();
+/// let manager = RefManager::from_fn(&DATA, move || format!("{}-local", prefix));
+/// manager.borrow_mut().push('!');
+/// assert!(manager.borrow().ends_with("-local!"));
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct RefManager<T: 'static> {
     local_key: &'static LocalKey<RefManagerDataGuard<T>>,
-    init_func: fn() -> T,
+    init_func: InitFunc<T>,
+    registry: Option<&'static SharedRegistry<T>>,
+    merge_func: Option<fn(T)>,
+    member: Option<&'static dyn ManagedStatic>,
+    epoch: Option<&'static AtomicUsize>,
+    mailbox: Option<&'static Mailbox<T>>,
+    reset_on_fork: bool,
+}
+
+enum InitFunc<T> {
+    Plain(fn() -> T),
+    WithContext(fn(&ThreadContext) -> T),
+    Closure(Box<dyn Fn() -> T + Send + Sync>),
 }
 
 #[derive(Debug)]
 pub struct BorrowError {
-    _private: (),
+    kind: BorrowErrorKind,
 }
 
 #[derive(Debug)]
 pub struct BorrowMutError {
-    _private: (),
+    kind: BorrowErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BorrowErrorKind {
+    Borrowed,
+    MutablyBorrowed,
+    Frozen,
+    Pinned,
+    Merged,
+    Unset,
 }
 
 #[macro_export]
@@ -77,52 +253,302 @@ macro_rules! _create_refmanager_data {
 
 impl<T> RefManager<T> {
     pub fn new(local_key: &'static LocalKey<RefManagerDataGuard<T>>, init_func: fn() -> T) -> Self {
+        Self::with_init(local_key, InitFunc::Plain(init_func))
+    }
+
+    pub fn with_context(
+        local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+        init_func: fn(&ThreadContext) -> T,
+    ) -> Self {
+        Self::with_init(local_key, InitFunc::WithContext(init_func))
+    }
+
+    /// Creates a manager whose initializer is a closure, which may capture state.
+    ///
+    /// The closure is shared by every thread that uses the manager.
+    pub fn from_fn<F>(local_key: &'static LocalKey<RefManagerDataGuard<T>>, init_func: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        Self::with_init(local_key, InitFunc::Closure(Box::new(init_func)))
+    }
+
+    fn with_init(
+        local_key: &'static LocalKey<RefManagerDataGuard<T>>,
+        init_func: InitFunc<T>,
+    ) -> Self {
         RefManager {
             local_key,
             init_func,
+            registry: None,
+            merge_func: None,
+            member: None,
+            epoch: None,
+            mailbox: None,
+            reset_on_fork: false,
+        }
+    }
+
+    /// Registers the value of every thread in `registry`, so that it can be visited from
+    /// other threads through `SharedRegistry::for_each_thread`.
+    pub fn shared(mut self, registry: &'static SharedRegistry<T>) -> Self
+    where
+        T: Send,
+    {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Hands the value to `merge_func` instead of dropping it, whenever it is destroyed or its
+    /// thread exits.
+    pub fn with_merge(mut self, merge_func: fn(T)) -> Self {
+        self.merge_func = Some(merge_func);
+        self
+    }
+
+    /// Records the value in the per-thread list used by groups and by
+    /// `destroy_all_on_current_thread()` whenever it is initialized.
+    pub fn registered(mut self, member: &'static dyn ManagedStatic) -> Self {
+        self.member = Some(member);
+        self
+    }
+
+    /// Rebuilds the value of a thread on its next access whenever `epoch` has changed since
+    /// the value was initialized. Values are not rebuilt while they are borrowed or frozen.
+    pub fn with_epoch(mut self, epoch: &'static AtomicUsize) -> Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    /// Rebuilds the value on its next access in the child process of a `fork()`, like a
+    /// change of the epoch, so that values and borrow counts inherited from the parent are
+    /// not reused. Has no effect on platforms without `fork()`.
+    pub fn reset_on_fork(mut self) -> Self {
+        self.reset_on_fork = true;
+        self
+    }
+
+    /// Runs the jobs posted to the current thread in `mailbox` on the value, the next time
+    /// it is accessed while not borrowed.
+    pub fn with_mailbox(mut self, mailbox: &'static Mailbox<T>) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    /// Runs the jobs posted to the current thread right away, initializing the value if
+    /// needed, and returns how many were run. Jobs are left queued during `with_frozen()`,
+    /// and discarded if the value has been frozen for good or pinned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are jobs to run and the value is currently borrowed.
+    pub fn drain_mailbox(&self) -> usize {
+        if !self.is_initialized() {
+            let _ = self.initialize();
+        }
+        self.local_key.with(|guard| self.run_mail(guard))
+    }
+
+    fn run_mail(&self, guard: &RefManagerDataGuard<T>) -> usize {
+        let mailbox = match self.mailbox {
+            Some(mailbox) if mailbox.has_pending() => mailbox,
+            _ => return 0,
+        };
+        // the value is writable again once `with_frozen()` returns
+        if guard
+            .inner_data()
+            .is_some_and(|inner_data| inner_data.frozen_scopes.get() != 0)
+        {
+            return 0;
+        }
+        let jobs = mailbox.take();
+        let count = jobs.len();
+        let mut value = match guard.try_borrow_mut() {
+            Ok(value) => value,
+            // a value that is frozen for good or pinned is never handed to the jobs, which
+            // are discarded instead
+            Err(ref err)
+                if err.kind == BorrowErrorKind::Frozen || err.kind == BorrowErrorKind::Pinned =>
+            {
+                return 0
+            }
+            Err(err) => panic!("{}", err),
+        };
+        for job in jobs {
+            job(&mut value);
+        }
+        count
+    }
+
+    // Both counters only grow, so their sum changes whenever one of them does.
+    fn current_epoch(&self) -> usize {
+        let epoch = self.epoch.map_or(0, |epoch| epoch.load(Ordering::Acquire));
+        if self.reset_on_fork {
+            epoch.wrapping_add(fork::fork_epoch())
+        } else {
+            epoch
+        }
+    }
+
+    fn install(&self, guard: &RefManagerDataGuard<T>, value: T, epoch: usize) -> Result<(), T> {
+        guard.install(value, self.registry, self.merge_func, epoch)?;
+        if self.reset_on_fork {
+            fork::register_atfork();
+        }
+        if let Some(mailbox) = self.mailbox {
+            mailbox::register_owner(mailbox);
+        }
+        if let Some(member) = self.member {
+            group::register(member);
+        }
+        Ok(())
+    }
+
+    /// Merges the current value with `merge_func` and replaces it with a new one.
+    ///
+    /// Fails if there is no value on the current thread, or if the manager has no
+    /// `merge_func`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[allow(clippy::result_unit_err)]
+    pub fn flush(&self) -> Result<(), ()> {
+        let merge_func = match self.merge_func {
+            Some(merge_func) if self.is_initialized() => merge_func,
+            _ => return Err(()),
+        };
+        let value = self.init_value();
+        merge_func(std::mem::replace(&mut *self.borrow_mut(), value));
+        Ok(())
+    }
+
+    fn init_value(&self) -> T {
+        match self.init_func {
+            InitFunc::Plain(init_func) => init_func(),
+            InitFunc::WithContext(init_func) => init_func(&ThreadContext::current()),
+            InitFunc::Closure(ref init_func) => init_func(),
         }
     }
 
     fn get_initialized_peek(&self) -> RefManagerPeekData<T> {
+        self.with_initialized_guard(|guard| guard.peek_data.get())
+    }
+
+    // Runs `f` on the guard within the same thread-local access that makes sure the value
+    // is initialized, current, and has run its mail.
+    fn with_initialized_guard<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&RefManagerDataGuard<T>) -> R,
+    {
         self.local_key.with(|guard| {
-            if guard.peek_data.get().ptr_inner_data.is_null() {
-                self.initialize().expect("failed to initialize");
+            match guard.inner_data() {
+                None => self.initialize().expect("failed to initialize"),
+                Some(inner_data)
+                    if inner_data.epoch != self.current_epoch()
+                        && inner_data.borrow_count.get() == 0
+                        && !inner_data.is_frozen() =>
+                {
+                    guard.destroy().expect("failed to destroy a stale value");
+                    // the initializer may have used the value itself
+                    let _ = self.initialize();
+                }
+                Some(_) => {}
             }
-            guard.peek_data.get()
+            let idle = |inner_data: &RefManagerInnerData<T>| {
+                inner_data.borrow_count.get() == 0 && !inner_data.is_frozen()
+            };
+            if self.mailbox.is_some() && guard.inner_data().is_some_and(idle) {
+                self.run_mail(guard);
+            }
+            f(guard)
         })
     }
+
+    pub fn share(&self) -> LocalRc<T> {
+        self.with_initialized_guard(|guard| guard.share())
+            .expect("failed to initialize")
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn freeze(&self) -> Result<(), ()> {
+        self.with_initialized_guard(|guard| guard.freeze())
+    }
+
+    pub unsafe fn unfreeze(&self) {
+        self.local_key.with(|guard| guard.unfreeze())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.local_key.with(|guard| guard.is_frozen())
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        self.local_key.with(|guard| guard.is_borrowed())
+    }
+
+    pub fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R {
+        self.with_initialized_guard(|guard| guard.with_frozen(f))
+            .expect("already mutably borrowed")
+    }
+
+    pub fn try_borrow_pin<'a>(&self) -> Result<Pin<RefMut<'a, T>>, BorrowMutError> {
+        self.with_initialized_guard(|guard| guard.try_borrow_pin())
+    }
+
+    pub fn downgrade(&self) -> LocalWeak<T> {
+        self.get_initialized_peek();
+        LocalWeak::new(self.local_key, self.generation())
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.get_initialized_value_ptr()
+    }
+
+    pub(crate) fn get_initialized_value_ptr(&self) -> *mut T {
+        self.get_initialized_peek().ptr_value
+    }
+}
+
+impl<T> Debug for InitFunc<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            InitFunc::Plain(init_func) => f.debug_tuple("Plain").field(&init_func).finish(),
+            InitFunc::WithContext(init_func) => {
+                f.debug_tuple("WithContext").field(&init_func).finish()
+            }
+            InitFunc::Closure(_) => f.write_str("Closure"),
+        }
+    }
 }
 
 impl<T> RefThreadLocal<T> for RefManager<T> {
     fn initialize(&self) -> Result<(), ()> {
         self.local_key.with(|guard| {
-            if guard.peek_data.get().ptr_inner_data.is_null() {
-                let mut box_inner_data = Box::new(RefManagerInnerData {
-                    borrow_count: Cell::new(0),
-                    value: (self.init_func)(),
-                });
-                let ptr_borrow_count = &box_inner_data.borrow_count as *const Cell<isize>;
-                let ptr_value = &mut box_inner_data.value as *mut T;
-                let ptr_inner_data = Box::into_raw(box_inner_data);
-                guard.peek_data.set(RefManagerPeekData {
-                    ptr_inner_data,
-                    ptr_borrow_count,
-                    ptr_value,
-                });
-                Ok(())
-            } else {
+            if guard.is_initialized() {
                 Err(())
+            } else {
+                let epoch = self.current_epoch();
+                self.install(guard, self.init_value(), epoch)
+                    .map_err(|_| ())
             }
         })
     }
 
     fn destroy(&self) -> Result<(), ()> {
-        self.local_key.with(|guard| guard.destroy())
+        self.local_key.with(|guard| guard.destroy())?;
+        if let Some(mailbox) = self.mailbox {
+            mailbox.disown();
+        }
+        Ok(())
     }
 
     fn is_initialized(&self) -> bool {
-        self.local_key
-            .with(|guard| !guard.peek_data.get().ptr_inner_data.is_null())
+        self.local_key.with(|guard| guard.is_initialized())
+    }
+
+    fn generation(&self) -> usize {
+        self.local_key.with(|guard| guard.generation())
     }
 
     fn borrow<'a>(&self) -> Ref<'a, T> {
@@ -130,45 +556,134 @@ impl<T> RefThreadLocal<T> for RefManager<T> {
     }
 
     fn borrow_mut<'a>(&self) -> RefMut<'a, T> {
-        self.try_borrow_mut().expect("already borrowed")
+        self.try_borrow_mut()
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 
     fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
-        let peek_data = self.get_initialized_peek();
-        let (ptr_borrow_count, ptr_value) = (peek_data.ptr_borrow_count, peek_data.ptr_value);
+        self.with_initialized_guard(|guard| guard.try_borrow())
+    }
+
+    fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        self.with_initialized_guard(|guard| guard.try_borrow_mut())
+    }
+}
 
-        let cell_borrow_count = unsafe { ptr_borrow_count.as_ref() }.unwrap();
-        let borrow_count = cell_borrow_count.get();
-        if borrow_count < 0 {
-            return Err(BorrowError { _private: () });
+impl<T> SetThreadLocal<T> for RefManager<T> {
+    fn set(&self, value: T) -> Result<(), T> {
+        self.local_key
+            .with(|guard| self.install(guard, value, self.current_epoch()))
+    }
+
+    fn take(&self) -> Option<T> {
+        let value = self.local_key.with(|guard| guard.take())?;
+        if let Some(mailbox) = self.mailbox {
+            mailbox.disown();
         }
-        cell_borrow_count.set(borrow_count + 1);
-        Ok(Ref {
-            borrow_count: cell_borrow_count,
-            value: unsafe { ptr_value.as_ref() }.unwrap(),
-        })
+        Some(value)
     }
+}
 
-    fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
-        let peek_data = self.get_initialized_peek();
-        let (ptr_borrow_count, ptr_value) = (peek_data.ptr_borrow_count, peek_data.ptr_value);
+impl<'a, T: ?Sized> Ref<'a, T> {
+    // Borrows a value that has its own borrow count, outside of a `RefManagerDataGuard`.
+    // `value` must stay valid for `'a` and only be accessed through `borrow_count`.
+    pub(crate) unsafe fn counted(
+        borrow_count: &'a BorrowCount,
+        value: *const T,
+    ) -> Result<Self, BorrowError> {
+        if borrow_count.try_borrow() {
+            Ok(Ref {
+                borrow_count: Some(borrow_count),
+                value: &*value,
+            })
+        } else {
+            Err(BorrowError {
+                kind: BorrowErrorKind::MutablyBorrowed,
+            })
+        }
+    }
+}
 
-        let cell_borrow_count = unsafe { ptr_borrow_count.as_ref() }.unwrap();
-        let borrow_count = cell_borrow_count.get();
-        if borrow_count != 0 {
-            return Err(BorrowMutError { _private: () });
+impl<'a, T: ?Sized> RefMut<'a, T> {
+    pub(crate) unsafe fn counted(
+        borrow_count: &'a BorrowCount,
+        value: *mut T,
+    ) -> Result<Self, BorrowMutError> {
+        if borrow_count.try_borrow_mut() {
+            Ok(RefMut {
+                borrow_count,
+                value: &mut *value,
+            })
+        } else {
+            Err(BorrowMutError {
+                kind: BorrowErrorKind::Borrowed,
+            })
+        }
+    }
+}
+
+impl BorrowError {
+    /// Returns `true` if the borrow failed because the value has not been set yet.
+    pub fn is_unset(&self) -> bool {
+        self.kind == BorrowErrorKind::Unset
+    }
+
+    pub(crate) fn unset() -> Self {
+        BorrowError {
+            kind: BorrowErrorKind::Unset,
+        }
+    }
+
+    pub(crate) fn description(&self) -> &'static str {
+        self.kind.description()
+    }
+}
+
+impl Display for BorrowError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(self.kind.description(), f)
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+impl BorrowMutError {
+    /// Returns `true` if the borrow failed because the value has not been set yet.
+    pub fn is_unset(&self) -> bool {
+        self.kind == BorrowErrorKind::Unset
+    }
+
+    pub(crate) fn description(&self) -> &'static str {
+        self.kind.description()
+    }
+}
+
+impl Display for BorrowMutError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(self.kind.description(), f)
+    }
+}
+
+impl std::error::Error for BorrowMutError {}
+
+impl BorrowErrorKind {
+    fn description(self) -> &'static str {
+        match self {
+            BorrowErrorKind::Borrowed => "already borrowed",
+            BorrowErrorKind::MutablyBorrowed => "already mutably borrowed",
+            BorrowErrorKind::Frozen => "value is frozen",
+            BorrowErrorKind::Pinned => "value is pinned",
+            BorrowErrorKind::Merged => "cannot pin a value with a merge function",
+            BorrowErrorKind::Unset => "value is not set",
         }
-        cell_borrow_count.set(-1);
-        Ok(RefMut {
-            borrow_count: cell_borrow_count,
-            value: unsafe { ptr_value.as_mut() }.unwrap(),
-        })
     }
 }
 
 impl<'a, T: ?Sized> Drop for Ref<'a, T> {
     fn drop(&mut self) {
-        self.borrow_count.set(self.borrow_count.get() - 1); // from positive to zero
+        if let Some(borrow_count) = self.borrow_count {
+            borrow_count.add(-1); // from positive to zero
+        }
     }
 }
 
@@ -189,7 +704,7 @@ impl<'a, T: ?Sized> Ref<'a, T> {
         let value = orig.value;
         std::mem::forget(orig);
         Ref {
-            borrow_count: borrow_count,
+            borrow_count,
             value: f(value),
         }
     }
@@ -202,14 +717,16 @@ impl<'a, T: ?Sized> Ref<'a, T> {
         let value = orig.value;
         std::mem::forget(orig);
         let (a, b) = f(value);
-        borrow_count.set(borrow_count.get() + 1);
+        if let Some(borrow_count) = borrow_count {
+            borrow_count.add(1);
+        }
         (
             Ref {
-                borrow_count: borrow_count,
+                borrow_count,
                 value: a,
             },
             Ref {
-                borrow_count: borrow_count,
+                borrow_count,
                 value: b,
             },
         )
@@ -228,9 +745,48 @@ impl<'a, T: Display> Display for Ref<'a, T> {
     }
 }
 
+impl<'a, T: ?Sized> Clone for FrozenRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T: ?Sized> Copy for FrozenRef<'a, T> {}
+
+impl<'a, T: ?Sized> Deref for FrozenRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> FrozenRef<'a, T> {
+    pub fn map<U: ?Sized, F>(orig: FrozenRef<'a, T>, f: F) -> FrozenRef<'a, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        FrozenRef {
+            value: f(orig.value),
+        }
+    }
+}
+
+impl<'a, T: ?Sized + Debug> Debug for FrozenRef<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Debug::fmt(self.value, f)
+    }
+}
+
+impl<'a, T: ?Sized + Display> Display for FrozenRef<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(self.value, f)
+    }
+}
+
 impl<'a, T: ?Sized> Drop for RefMut<'a, T> {
     fn drop(&mut self) {
-        self.borrow_count.set(self.borrow_count.get() + 1); // from negative to zero
+        self.borrow_count.add(1); // from negative to zero
     }
 }
 
@@ -269,12 +825,15 @@ impl<'a, T: ?Sized> RefMut<'a, T> {
         let value = orig.value as *mut T;
         std::mem::forget(orig);
         RefMut {
-            borrow_count: borrow_count,
+            borrow_count,
             value: f(unsafe { value.as_mut().unwrap() }),
         }
     }
 
-    pub fn map_split<U: ?Sized, V: ?Sized, F>(orig: RefMut<'a, T>, f: F) -> (RefMut<'a, U>, RefMut<'a, V>)
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        orig: RefMut<'a, T>,
+        f: F,
+    ) -> (RefMut<'a, U>, RefMut<'a, V>)
     where
         F: FnOnce(&mut T) -> (&mut U, &mut V),
     {
@@ -282,50 +841,335 @@ impl<'a, T: ?Sized> RefMut<'a, T> {
         let value = orig.value as *mut T;
         std::mem::forget(orig);
         let (a, b) = f(unsafe { value.as_mut().unwrap() });
-        borrow_count.set(borrow_count.get() - 1);
+        borrow_count.add(-1);
         (
             RefMut {
-                borrow_count: borrow_count,
+                borrow_count,
                 value: a,
             },
             RefMut {
-                borrow_count: borrow_count,
+                borrow_count,
                 value: b,
             },
         )
     }
 }
 
+impl BorrowCount {
+    pub(crate) fn get(&self) -> isize {
+        match *self {
+            BorrowCount::Local(ref count) => count.get(),
+            BorrowCount::Shared(ref count) => count.get(),
+        }
+    }
+
+    pub(crate) fn add(&self, delta: isize) {
+        match *self {
+            BorrowCount::Local(ref count) => count.set(count.get() + delta),
+            BorrowCount::Shared(ref count) => count.add(delta),
+        }
+    }
+
+    pub(crate) fn try_borrow(&self) -> bool {
+        match *self {
+            BorrowCount::Local(ref count) => {
+                let borrow_count = count.get();
+                if borrow_count < 0 {
+                    return false;
+                }
+                count.set(borrow_count + 1);
+                true
+            }
+            BorrowCount::Shared(ref count) => count.try_borrow(),
+        }
+    }
+
+    pub(crate) fn try_borrow_mut(&self) -> bool {
+        match *self {
+            BorrowCount::Local(ref count) => {
+                if count.get() != 0 {
+                    return false;
+                }
+                count.set(-1);
+                true
+            }
+            BorrowCount::Shared(ref count) => count.try_borrow_mut(),
+        }
+    }
+
+    fn shared(&self) -> Option<&SharedCount> {
+        match *self {
+            BorrowCount::Local(_) => None,
+            BorrowCount::Shared(ref count) => Some(count),
+        }
+    }
+}
+
 impl<T> RefManagerDataGuard<T> {
     pub const INIT_PEEK_DATA: RefManagerPeekData<T> = RefManagerPeekData {
         ptr_inner_data: null_mut(),
-        ptr_borrow_count: null(),
         ptr_value: null_mut(),
     };
+    #[allow(clippy::declare_interior_mutable_const)]
     pub const INIT_SELF: Self = RefManagerDataGuard {
         peek_data: Cell::new(Self::INIT_PEEK_DATA),
+        generation: Cell::new(0),
     };
 
+    pub(crate) fn is_initialized(&self) -> bool {
+        !self.peek_data.get().ptr_inner_data.is_null()
+    }
+
+    pub(crate) fn generation(&self) -> usize {
+        self.generation.get()
+    }
+
+    fn bump_generation(&self) {
+        self.generation.set(self.generation.get().wrapping_add(1));
+    }
+
+    // Installs `value` if the slot is empty, handing it back otherwise.
+    pub(crate) fn set(&self, value: T) -> Result<(), T> {
+        self.install(value, None, None, 0)
+    }
+
+    fn install(
+        &self,
+        value: T,
+        registry: Option<&SharedRegistry<T>>,
+        merge_func: Option<fn(T)>,
+        epoch: usize,
+    ) -> Result<(), T> {
+        if self.is_initialized() {
+            return Err(value);
+        }
+        let shared_count = registry.map(|_| Arc::new(SharedCount::new()));
+        let borrow_count = match shared_count {
+            Some(ref count) => BorrowCount::Shared(count.clone()),
+            None => BorrowCount::Local(Cell::new(0)),
+        };
+        let box_inner_data = Box::new(RefManagerInnerData {
+            borrow_count,
+            frozen: Cell::new(false),
+            frozen_scopes: Cell::new(0),
+            options: if registry.is_some() || merge_func.is_some() {
+                Some(Box::new(InnerOptions {
+                    registry: registry.map_or(null(), |registry| registry as *const _),
+                    merge_func,
+                }))
+            } else {
+                None
+            },
+            epoch,
+            pinned: Cell::new(false),
+            shares: Cell::new(0),
+            detached: Cell::new(false),
+            value: UnsafeCell::new(value),
+        });
+        let ptr_inner_data = Box::into_raw(box_inner_data);
+        let ptr_value = unsafe { (*ptr_inner_data).value.get() };
+        if let (Some(registry), Some(count)) = (registry, shared_count) {
+            registry.register(thread::current().id(), ptr_inner_data, count);
+        }
+        self.peek_data.set(RefManagerPeekData {
+            ptr_inner_data,
+            ptr_value,
+        });
+        self.bump_generation();
+        Ok(())
+    }
+
+    fn inner_data(&self) -> Option<&RefManagerInnerData<T>> {
+        unsafe { self.peek_data.get().ptr_inner_data.as_ref() }
+    }
+
+    pub(crate) fn freeze(&self) -> Result<(), ()> {
+        match self.inner_data() {
+            // other threads may be reading a shared value, which frozen borrows would ignore
+            Some(inner_data)
+                if !inner_data.frozen.get()
+                    && inner_data.registry().is_none()
+                    && inner_data.borrow_count.get() >= 0 =>
+            {
+                inner_data.frozen.set(true);
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    // Frozen references are not counted, so the caller has to prove that none is alive.
+    pub(crate) unsafe fn unfreeze(&self) {
+        if let Some(inner_data) = self.inner_data() {
+            inner_data.frozen.set(false);
+        }
+    }
+
+    pub(crate) fn is_borrowed(&self) -> bool {
+        self.inner_data()
+            .is_some_and(|inner_data| inner_data.borrow_count.get() != 0)
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.inner_data()
+            .is_some_and(|inner_data| inner_data.is_frozen())
+    }
+
+    // Keeps the value from being written or destroyed while `f` runs, and hands it to `f`
+    // without counting, as `FrozenRef`s cannot outlive `f`. Borrows taken meanwhile are
+    // counted as usual, since they may. A shared value is borrowed once for all of `f`
+    // instead, since other threads only wait for counted borrows before visiting it.
+    pub(crate) fn with_frozen<R, F>(&self, f: F) -> Result<R, BorrowError>
+    where
+        F: FnOnce(FrozenRef<'_, T>) -> R,
+    {
+        let inner_data = self.inner_data().ok_or(BorrowError {
+            kind: BorrowErrorKind::Unset,
+        })?;
+        let borrow_count = inner_data.registry().map(|_| &inner_data.borrow_count);
+        let borrowed = match borrow_count {
+            Some(borrow_count) => borrow_count.try_borrow(),
+            None => inner_data.borrow_count.get() >= 0,
+        };
+        if !borrowed {
+            return Err(BorrowError {
+                kind: BorrowErrorKind::MutablyBorrowed,
+            });
+        }
+        let frozen_scopes = &inner_data.frozen_scopes;
+        frozen_scopes.set(frozen_scopes.get() + 1);
+        let _scope = FrozenScope {
+            frozen_scopes,
+            borrow_count,
+        };
+        Ok(f(FrozenRef {
+            value: unsafe { &*inner_data.value.get() },
+        }))
+    }
+
+    pub(crate) fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        match unsafe { self.peek_data.get().ptr_inner_data.as_ref() } {
+            None => Err(BorrowError {
+                kind: BorrowErrorKind::Unset,
+            }),
+            Some(inner_data) => inner_data.try_borrow(),
+        }
+    }
+
+    pub(crate) fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        let ptr_inner_data = self.peek_data.get().ptr_inner_data;
+        if ptr_inner_data.is_null() {
+            Err(BorrowMutError {
+                kind: BorrowErrorKind::Unset,
+            })
+        } else {
+            unsafe { RefManagerInnerData::try_borrow_mut(ptr_inner_data) }
+        }
+    }
+
+    pub(crate) fn try_borrow_pin<'a>(&self) -> Result<Pin<RefMut<'a, T>>, BorrowMutError> {
+        let ptr_inner_data = self.peek_data.get().ptr_inner_data;
+        if ptr_inner_data.is_null() {
+            Err(BorrowMutError {
+                kind: BorrowErrorKind::Unset,
+            })
+        } else {
+            unsafe { RefManagerInnerData::try_borrow_pin(ptr_inner_data) }
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut T {
+        self.peek_data.get().ptr_value
+    }
+
+    #[allow(clippy::result_unit_err)]
     pub fn destroy(&self) -> Result<(), ()> {
-        let peek_data = self.peek_data.get();
-        let (ptr_inner_data, ptr_borrow_count) =
-            (peek_data.ptr_inner_data, peek_data.ptr_borrow_count);
+        self.destroy_impl(false)
+    }
+
+    fn destroy_impl(&self, thread_exiting: bool) -> Result<(), ()> {
+        let ptr_inner_data = self.remove(thread_exiting).ok_or(())?;
+        let inner_data = unsafe { &*ptr_inner_data };
+        if inner_data.is_frozen() {
+            // references handed out while frozen are not counted, and may still be used by
+            // the thread's other destructors, so the value is leaked instead
+            inner_data.detached.set(true);
+        } else if inner_data.shares.get() == 0 {
+            RefManagerInnerData::release(ptr_inner_data);
+        } else {
+            // a shared value is dropped by its last `LocalRc` instead
+            inner_data.detached.set(true);
+        }
+        Ok(())
+    }
+
+    // Moves the value out of the slot, without merging it.
+    pub(crate) fn take(&self) -> Option<T> {
+        let inner_data = self.inner_data()?;
+        if inner_data.shares.get() != 0 {
+            panic!("cannot move out of a shared value");
+        }
+        if inner_data.pinned.get() {
+            panic!("cannot move out of a pinned value");
+        }
+        let ptr_inner_data = self.remove(false)?;
+        Some(unsafe { Box::from_raw(ptr_inner_data) }.value.into_inner())
+    }
+
+    pub(crate) fn share(&self) -> Option<LocalRc<T>> {
+        self.inner_data()?;
+        Some(unsafe { LocalRc::new(self.peek_data.get().ptr_inner_data) })
+    }
+
+    fn remove(&self, thread_exiting: bool) -> Option<*mut RefManagerInnerData<T>> {
+        let ptr_inner_data = self.peek_data.get().ptr_inner_data;
         if ptr_inner_data.is_null() {
-            Err(())
+            None
         } else {
-            let borrow_count = unsafe { ptr_borrow_count.as_ref() }.unwrap().get();
-            if borrow_count != 0 {
+            let inner_data = unsafe { ptr_inner_data.as_ref() }.unwrap();
+            // references handed out while frozen are not counted, so nothing can prove
+            // that none of them are alive, and the value is leaked when the thread exits
+            if !thread_exiting && inner_data.is_frozen() {
+                panic!("cannot destroy a frozen value");
+            }
+            // once the removal has started, no other thread can be visiting a shared value
+            let shared_count = inner_data.borrow_count.shared();
+            if let Some(count) = shared_count {
+                count.start_removal();
+            }
+            // a shared value outlives the slot, and with it the references into it
+            if inner_data.borrow_count.get() != 0 && inner_data.shares.get() == 0 {
+                if let Some(count) = shared_count {
+                    count.cancel_removal();
+                }
                 panic!("cannot destroy before all references are dropped");
             }
-            unsafe { Box::from_raw(ptr_inner_data) };
+            if let Some(registry) = inner_data.registry() {
+                registry.unregister(ptr_inner_data);
+            }
             self.peek_data.set(Self::INIT_PEEK_DATA);
-            Ok(())
+            self.bump_generation();
+            Some(ptr_inner_data)
+        }
+    }
+}
+
+struct FrozenScope<'a> {
+    frozen_scopes: &'a Cell<usize>,
+    borrow_count: Option<&'a BorrowCount>,
+}
+
+impl<'a> Drop for FrozenScope<'a> {
+    fn drop(&mut self) {
+        self.frozen_scopes.set(self.frozen_scopes.get() - 1);
+        if let Some(borrow_count) = self.borrow_count {
+            borrow_count.add(-1);
         }
     }
 }
 
 impl<T> Drop for RefManagerDataGuard<T> {
     fn drop(&mut self) {
-        let _ = self.destroy();
+        let _ = self.destroy_impl(true);
     }
 }
@@ -0,0 +1,72 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::ScopedThreadLocal;
+use refmanager::BorrowError;
+use std::cell::Cell;
+use std::thread::LocalKey;
+
+// A `static scoped` does not own its value: the slot only points to a value lent by
+// `set()`, and is reset before `set()` returns.
+#[derive(Debug)]
+pub struct ScopedManager<T: ?Sized + 'static> {
+    local_key: &'static LocalKey<Cell<Option<*const T>>>,
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _create_scopedmanager_data {
+    ($NAME:ident, $T:ty) => {
+        thread_local! {
+          static $NAME: ::std::cell::Cell<::std::option::Option<*const $T>> =
+            const { ::std::cell::Cell::new(::std::option::Option::None) };
+        }
+    };
+}
+
+impl<T: ?Sized> ScopedManager<T> {
+    pub fn new(local_key: &'static LocalKey<Cell<Option<*const T>>>) -> Self {
+        ScopedManager { local_key }
+    }
+}
+
+struct Reset<T: ?Sized + 'static> {
+    local_key: &'static LocalKey<Cell<Option<*const T>>>,
+    previous: Option<*const T>,
+}
+
+impl<T: ?Sized> Drop for Reset<T> {
+    fn drop(&mut self) {
+        let previous = self.previous;
+        self.local_key.with(|ptr| ptr.set(previous));
+    }
+}
+
+impl<T: ?Sized> ScopedThreadLocal<T> for ScopedManager<T> {
+    fn set<R, F: FnOnce() -> R>(&self, value: &T, f: F) -> R {
+        let previous = self
+            .local_key
+            .with(|ptr| ptr.replace(Some(value as *const T)));
+        let _reset = Reset {
+            local_key: self.local_key,
+            previous,
+        };
+        f()
+    }
+
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<R, BorrowError> {
+        match self.local_key.with(|ptr| ptr.get()) {
+            // the value outlives the `set()` call that is still running further up the stack
+            Some(value) => Ok(f(unsafe { &*value })),
+            None => Err(BorrowError::unset()),
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.local_key.with(|ptr| ptr.get().is_some())
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use refmanager::{BorrowCount, RefManagerInnerData};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+/// The process-wide list of every thread's value of a `managed(shared)` static.
+///
+/// Values are added when a thread initializes them and removed before they are dropped,
+/// either by `destroy()` or by their thread exiting.
+pub struct SharedRegistry<T> {
+    entries: Mutex<Vec<SharedEntry<T>>>,
+}
+
+struct SharedEntry<T> {
+    thread_id: ThreadId,
+    inner_data: *const RefManagerInnerData<T>,
+    // outlives the value, so that a visitor can tell whether the value is still there
+    count: Arc<SharedCount>,
+}
+
+impl<T> Clone for SharedEntry<T> {
+    fn clone(&self) -> Self {
+        SharedEntry {
+            thread_id: self.thread_id,
+            inner_data: self.inner_data,
+            count: self.count.clone(),
+        }
+    }
+}
+
+// Values are only touched by other threads while their owner is kept from borrowing them,
+// which is the same guarantee a `Mutex<T>` gives.
+unsafe impl<T: Send> Sync for SharedRegistry<T> {}
+unsafe impl<T: Send> Send for SharedRegistry<T> {}
+
+impl<T> SharedRegistry<T> {
+    pub const fn new() -> Self {
+        SharedRegistry {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<SharedEntry<T>>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn register(
+        &self,
+        thread_id: ThreadId,
+        inner_data: *const RefManagerInnerData<T>,
+        count: Arc<SharedCount>,
+    ) {
+        self.lock().push(SharedEntry {
+            thread_id,
+            inner_data,
+            count,
+        });
+    }
+
+    pub(crate) fn unregister(&self, inner_data: *const RefManagerInnerData<T>) {
+        self.lock()
+            .retain(|entry| !std::ptr::eq(entry.inner_data, inner_data));
+    }
+
+    /// Calls `f` with the value of every thread that currently has one.
+    ///
+    /// Each value is visited while its thread is kept from borrowing it: a thread that
+    /// tries to borrow its value during the visit waits until `f` returns, and the visit
+    /// waits for a thread to release its borrows first. The registry is only locked while
+    /// the values are listed, so threads can initialize, destroy and exit meanwhile: values
+    /// initialized during the call are not visited, and values destroyed before their turn,
+    /// or while the visit waits for them, are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread's own value is mutably borrowed.
+    pub fn for_each_thread<F>(&self, mut f: F)
+    where
+        F: FnMut(ThreadId, &T),
+    {
+        let current_thread_id = thread::current().id();
+        let entries = self.lock().clone();
+        for entry in entries {
+            let _visit = if entry.thread_id == current_thread_id {
+                // `f` may have destroyed the value of the current thread
+                if entry.count.is_removed() {
+                    continue;
+                }
+                Visit::local(unsafe { &(*entry.inner_data).borrow_count })
+            } else if entry.count.lock_remote() {
+                Visit::remote(&entry.count)
+            } else {
+                continue;
+            };
+            f(entry.thread_id, unsafe {
+                &*(*entry.inner_data).value.get()
+            });
+        }
+    }
+}
+
+impl<T> Debug for SharedRegistry<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedRegistry")
+            .field("threads", &self.lock().len())
+            .finish()
+    }
+}
+
+impl<T> Default for SharedRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The borrow count of a `managed(shared)` value, which is marked with `REMOTE_BORROW` while
+// another thread visits the value. Threads wait for each other on `changed`, which is
+// notified whenever the count returns to zero while someone is waiting for it.
+pub(crate) struct SharedCount {
+    count: AtomicIsize,
+    // the number of visitors waiting for the owner to release its borrows
+    waiters: AtomicUsize,
+    // set while the owner removes the value, which is no longer visited from then on
+    removed: Mutex<bool>,
+    changed: Condvar,
+}
+
+const REMOTE_BORROW: isize = isize::MIN;
+
+impl SharedCount {
+    pub(crate) fn new() -> Self {
+        SharedCount {
+            count: AtomicIsize::new(0),
+            waiters: AtomicUsize::new(0),
+            removed: Mutex::new(false),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        self.removed.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn wait<'a>(&self, removed: MutexGuard<'a, bool>) -> MutexGuard<'a, bool> {
+        self.changed
+            .wait(removed)
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn get(&self) -> isize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn add(&self, delta: isize) {
+        let count = self.count.fetch_add(delta, Ordering::SeqCst) + delta;
+        if count == 0 && self.waiters.load(Ordering::SeqCst) != 0 {
+            let _removed = self.lock();
+            self.changed.notify_all();
+        }
+    }
+
+    pub(crate) fn try_borrow(&self) -> bool {
+        loop {
+            let borrow_count = self.count.load(Ordering::Relaxed);
+            if borrow_count == REMOTE_BORROW {
+                self.wait_for_visit();
+            } else if borrow_count < 0 {
+                return false;
+            } else if self
+                .count
+                .compare_exchange_weak(
+                    borrow_count,
+                    borrow_count + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+
+    pub(crate) fn try_borrow_mut(&self) -> bool {
+        loop {
+            match self
+                .count
+                .compare_exchange_weak(0, -1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(REMOTE_BORROW) => self.wait_for_visit(),
+                Err(borrow_count) if borrow_count != 0 => return false,
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn wait_for_visit(&self) {
+        let mut removed = self.lock();
+        while self.count.load(Ordering::Acquire) == REMOTE_BORROW {
+            removed = self.wait(removed);
+        }
+    }
+
+    // Waits until the owning thread holds no borrow, then keeps it from taking any. Returns
+    // `false` instead if the owner starts removing the value first.
+    fn lock_remote(&self) -> bool {
+        let mut removed = self.lock();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let locked = loop {
+            if *removed {
+                break false;
+            }
+            if self
+                .count
+                .compare_exchange(0, REMOTE_BORROW, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+            {
+                break true;
+            }
+            removed = self.wait(removed);
+        };
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        locked
+    }
+
+    fn unlock_remote(&self) {
+        let _removed = self.lock();
+        self.count.store(0, Ordering::Release);
+        self.changed.notify_all();
+    }
+
+    fn is_removed(&self) -> bool {
+        *self.lock()
+    }
+
+    // Called by the owner before it removes the value: no visit starts from then on, and
+    // the one in progress, if any, is waited for.
+    pub(crate) fn start_removal(&self) {
+        let mut removed = self.lock();
+        *removed = true;
+        self.changed.notify_all();
+        while self.count.load(Ordering::Acquire) == REMOTE_BORROW {
+            removed = self.wait(removed);
+        }
+    }
+
+    // Called by the owner when the value turns out to be still borrowed, and stays.
+    pub(crate) fn cancel_removal(&self) {
+        *self.lock() = false;
+    }
+}
+
+struct Visit<'a> {
+    borrow_count: Option<&'a BorrowCount>,
+    count: Option<&'a SharedCount>,
+}
+
+impl<'a> Visit<'a> {
+    fn local(borrow_count: &'a BorrowCount) -> Self {
+        if !borrow_count.try_borrow() {
+            panic!("already mutably borrowed");
+        }
+        Visit {
+            borrow_count: Some(borrow_count),
+            count: None,
+        }
+    }
+
+    // `count` must have been locked by `lock_remote()`.
+    fn remote(count: &'a SharedCount) -> Self {
+        Visit {
+            borrow_count: None,
+            count: Some(count),
+        }
+    }
+}
+
+impl<'a> Drop for Visit<'a> {
+    fn drop(&mut self) {
+        if let Some(count) = self.count {
+            count.unlock_remote();
+        }
+        if let Some(borrow_count) = self.borrow_count {
+            borrow_count.add(-1);
+        }
+    }
+}
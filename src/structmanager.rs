@@ -0,0 +1,242 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::StructThreadLocal;
+use group::{self, ManagedStatic};
+use refmanager::{BorrowCount, BorrowError, BorrowMutError, Ref, RefMut};
+use std::cell::{Cell, UnsafeCell};
+use std::fmt::{Debug, Formatter};
+use std::ptr::null_mut;
+use std::thread::LocalKey;
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _create_structmanager_data {
+    ($NAME:ident, $V:ty) => {
+        thread_local! {
+          static $NAME: $crate::StructSlot<$V> = const { $crate::StructSlot::INIT_SELF };
+        }
+    };
+}
+
+type IsBorrowedFunc<V> = fn(&V) -> bool;
+
+// The single thread-local slot of a `static managed group`, holding every field at once.
+pub struct StructSlot<V> {
+    values: Cell<*mut V>,
+    is_borrowed: Cell<Option<IsBorrowedFunc<V>>>,
+}
+
+impl<V> StructSlot<V> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const INIT_SELF: Self = StructSlot {
+        values: Cell::new(null_mut()),
+        is_borrowed: Cell::new(None),
+    };
+
+    fn destroy(&self) -> Result<(), ()> {
+        let values = self.values.get();
+        if values.is_null() {
+            return Err(());
+        }
+        if let Some(is_borrowed) = self.is_borrowed.get() {
+            if is_borrowed(unsafe { &*values }) {
+                panic!("cannot destroy before all references are dropped");
+            }
+        }
+        self.values.set(null_mut());
+        drop(unsafe { Box::from_raw(values) });
+        Ok(())
+    }
+}
+
+impl<V> Drop for StructSlot<V> {
+    fn drop(&mut self) {
+        let _ = self.destroy();
+    }
+}
+
+/// A field of a `static managed group`, with its own borrow count.
+pub struct FieldCell<T> {
+    borrow_count: BorrowCount,
+    value: UnsafeCell<T>,
+}
+
+impl<T> FieldCell<T> {
+    pub fn new(value: T) -> Self {
+        FieldCell {
+            borrow_count: BorrowCount::Local(Cell::new(0)),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        self.borrow_count.get() != 0
+    }
+}
+
+pub struct StructManager<V: 'static> {
+    local_key: &'static LocalKey<StructSlot<V>>,
+    init_func: fn() -> V,
+    is_borrowed: fn(&V) -> bool,
+    member: Option<&'static dyn ManagedStatic>,
+}
+
+impl<V> StructManager<V> {
+    pub fn new(
+        local_key: &'static LocalKey<StructSlot<V>>,
+        init_func: fn() -> V,
+        is_borrowed: fn(&V) -> bool,
+    ) -> Self {
+        StructManager {
+            local_key,
+            init_func,
+            is_borrowed,
+            member: None,
+        }
+    }
+
+    /// Records the group in the per-thread list used by `destroy_all_on_current_thread()`
+    /// whenever it is initialized.
+    pub fn registered(mut self, member: &'static dyn ManagedStatic) -> Self {
+        self.member = Some(member);
+        self
+    }
+
+    /// Returns `true` if any field is borrowed on the current thread.
+    pub fn is_borrowed(&self) -> bool {
+        self.local_key.with(|slot| {
+            let values = slot.values.get();
+            !values.is_null() && (self.is_borrowed)(unsafe { &*values })
+        })
+    }
+
+    pub fn get_initialized_values(&self) -> *const V {
+        self.local_key.with(|slot| {
+            if slot.values.get().is_null() {
+                self.initialize().expect("failed to initialize");
+            }
+            slot.values.get() as *const V
+        })
+    }
+}
+
+impl<V> StructThreadLocal for StructManager<V> {
+    fn initialize(&self) -> Result<(), ()> {
+        if self.is_initialized() {
+            return Err(());
+        }
+        let values = Box::into_raw(Box::new((self.init_func)()));
+        self.local_key.with(|slot| {
+            if slot.values.get().is_null() {
+                slot.is_borrowed.set(Some(self.is_borrowed));
+                slot.values.set(values);
+                Ok(())
+            } else {
+                // the initializer initialized the group itself
+                drop(unsafe { Box::from_raw(values) });
+                Err(())
+            }
+        })?;
+        if let Some(member) = self.member {
+            group::register(member);
+        }
+        Ok(())
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        self.local_key.with(|slot| slot.destroy())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.local_key.with(|slot| !slot.values.get().is_null())
+    }
+}
+
+// Lets the type generated for a `static managed group` reach its manager, whose value type
+// is only nameable inside the static's initializer.
+#[doc(hidden)]
+pub struct StructHandle {
+    initialize: fn() -> Result<(), ()>,
+    destroy: fn() -> Result<(), ()>,
+    is_initialized: fn() -> bool,
+    is_borrowed: fn() -> bool,
+}
+
+impl StructHandle {
+    pub const fn new(
+        initialize: fn() -> Result<(), ()>,
+        destroy: fn() -> Result<(), ()>,
+        is_initialized: fn() -> bool,
+        is_borrowed: fn() -> bool,
+    ) -> Self {
+        StructHandle {
+            initialize,
+            destroy,
+            is_initialized,
+            is_borrowed,
+        }
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        (self.is_borrowed)()
+    }
+}
+
+impl StructThreadLocal for StructHandle {
+    fn initialize(&self) -> Result<(), ()> {
+        (self.initialize)()
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        (self.destroy)()
+    }
+
+    fn is_initialized(&self) -> bool {
+        (self.is_initialized)()
+    }
+}
+
+/// A field of a `static managed group`, borrowed like a `static managed` variable.
+///
+/// Borrowing a field initializes the whole group on the current thread if needed. Each
+/// field has its own borrow count, so different fields can be borrowed mutably at the same
+/// time.
+pub struct StructField<T: 'static> {
+    cell: fn() -> *const FieldCell<T>,
+}
+
+impl<T> StructField<T> {
+    #[doc(hidden)]
+    pub const fn new(cell: fn() -> *const FieldCell<T>) -> Self {
+        StructField { cell }
+    }
+
+    pub fn borrow<'a>(&self) -> Ref<'a, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    pub fn borrow_mut<'a>(&self) -> RefMut<'a, T> {
+        self.try_borrow_mut().expect("already borrowed")
+    }
+
+    pub fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        let cell = unsafe { &*(self.cell)() };
+        unsafe { Ref::counted(&cell.borrow_count, cell.value.get()) }
+    }
+
+    pub fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        let cell = unsafe { &*(self.cell)() };
+        unsafe { RefMut::counted(&cell.borrow_count, cell.value.get()) }
+    }
+}
+
+impl<T> Debug for StructField<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("StructField")
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::SetThreadLocal;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The future returned by `TaskLocalThreadLocal::scope()`.
+///
+/// Each `poll()` moves the task's value into the thread-local slot, polls the inner future,
+/// and moves the value back out, restoring the value (or the uninitialized state) that the
+/// slot had before. Changes made by the task are kept from one poll to the next.
+#[must_use = "futures do nothing unless polled"]
+pub struct TaskScope<T, F, S: 'static> {
+    local: &'static S,
+    value: Option<T>,
+    future: F,
+}
+
+impl<T, F, S: SetThreadLocal<T>> TaskScope<T, F, S> {
+    pub(crate) fn new(local: &'static S, value: T, future: F) -> Self {
+        TaskScope {
+            local,
+            value: Some(value),
+            future,
+        }
+    }
+}
+
+// Moves the task's value back into its `TaskScope` when dropped, including while unwinding.
+struct Installed<'a, T, S: SetThreadLocal<T> + 'static> {
+    local: &'static S,
+    previous: Option<T>,
+    value: &'a mut Option<T>,
+}
+
+impl<'a, T, S: SetThreadLocal<T>> Installed<'a, T, S> {
+    fn new(local: &'static S, value: &'a mut Option<T>) -> Self {
+        let previous = local.take();
+        if let Some(value) = value.take() {
+            if local.set(value).is_err() {
+                unreachable!();
+            }
+        }
+        Installed {
+            local,
+            previous,
+            value,
+        }
+    }
+}
+
+impl<'a, T, S: SetThreadLocal<T>> Drop for Installed<'a, T, S> {
+    fn drop(&mut self) {
+        *self.value = self.local.take();
+        if let Some(previous) = self.previous.take() {
+            if self.local.set(previous).is_err() {
+                unreachable!();
+            }
+        }
+    }
+}
+
+impl<T, F, S> Future for TaskScope<T, F, S>
+where
+    F: Future,
+    S: SetThreadLocal<T>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // only `future` is structurally pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let _installed = Installed::new(this.local, &mut this.value);
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
+
+impl<T, F, S> Debug for TaskScope<T, F, S> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("TaskScope")
+            .field("has_value", &self.value.is_some())
+            .finish()
+    }
+}
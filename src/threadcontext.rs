@@ -0,0 +1,101 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use std::sync::Mutex;
+use std::thread::{self, Thread, ThreadId};
+
+/// Describes the thread a value is being initialized on.
+///
+/// This is what the initializer of a `static managed(context) NAME: T = |ctx| EXPR;` receives.
+#[derive(Debug, Clone)]
+pub struct ThreadContext {
+    thread: Thread,
+    index: usize,
+}
+
+impl ThreadContext {
+    pub fn current() -> Self {
+        ThreadContext {
+            thread: thread::current(),
+            index: THREAD_INDEX
+                .try_with(|thread_index| thread_index.index)
+                .unwrap_or_else(|_| ThreadIndex::acquire_for_teardown()),
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.thread.id()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.thread.name()
+    }
+
+    /// A small number identifying the thread among the live threads of the process.
+    ///
+    /// Indices are assigned from 0 on first use and stay the same for the lifetime of the
+    /// thread. The index of an exited thread is handed out again, so the indices in use
+    /// stay close to the number of live threads. Values initialized by the destructors of
+    /// other thread-locals, once the thread has released its index, get a new index that
+    /// is never handed out again.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+struct ThreadIndices {
+    next: usize,
+    free: Vec<usize>,
+}
+
+static THREAD_INDICES: Mutex<ThreadIndices> = Mutex::new(ThreadIndices {
+    next: 0,
+    free: Vec::new(),
+});
+
+struct ThreadIndex {
+    index: usize,
+}
+
+impl ThreadIndex {
+    fn acquire() -> Self {
+        let mut indices = THREAD_INDICES.lock().unwrap_or_else(|err| err.into_inner());
+        let smallest_free = indices
+            .free
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, index)| *index)
+            .map(|(position, _)| position);
+        let index = match smallest_free {
+            Some(position) => indices.free.swap_remove(position),
+            None => {
+                indices.next += 1;
+                indices.next - 1
+            }
+        };
+        ThreadIndex { index }
+    }
+
+    // Hands out an index once the thread's own has been released by its destructor, which
+    // is never reused, since nothing is left to release it when the thread is gone.
+    fn acquire_for_teardown() -> usize {
+        let mut indices = THREAD_INDICES.lock().unwrap_or_else(|err| err.into_inner());
+        indices.next += 1;
+        indices.next - 1
+    }
+}
+
+impl Drop for ThreadIndex {
+    fn drop(&mut self) {
+        let mut indices = THREAD_INDICES.lock().unwrap_or_else(|err| err.into_inner());
+        indices.free.push(self.index);
+    }
+}
+
+thread_local! {
+    static THREAD_INDEX: ThreadIndex = ThreadIndex::acquire();
+}
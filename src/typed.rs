@@ -0,0 +1,201 @@
+// Copyright 2018 tuxzz and lazy-static.rs Developers
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+extern crate std;
+use super::{FreezeThreadLocal, RefThreadLocal, SetThreadLocal};
+use refmanager::{BorrowError, BorrowMutError, FrozenRef, Ref, RefManagerDataGuard, RefMut};
+use std::any::{self, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+/// The current thread's value of type `T` in the per-thread type map.
+///
+/// Obtained with `typed::<T>()` or `Typed::new()`. Each type has one slot per thread, which
+/// is shared by every handle for that type, and is initialized on first use by the
+/// initializer registered with `register_typed_initializer()`, or else by the handle's own.
+///
+/// ```rust
+/// use ref_thread_local::{typed, RefThreadLocal};
+///
+/// fn count_calls<T: 'static>() -> usize {
+///     struct Calls<T>(usize, std::marker::PhantomData<T>);
+///     impl<T> Default for Calls<T> {
+///         fn default() -> Self {
+///             Calls(0, std::marker::PhantomData)
+///         }
+///     }
+///     let calls = typed::<Calls<T>>();
+///     calls.borrow_mut().0 += 1;
+///     let count = calls.borrow().0;
+///     count
+/// }
+///
+/// assert_eq!(count_calls::<u8>(), 1);
+/// assert_eq!(count_calls::<u8>(), 2);
+/// assert_eq!(count_calls::<u16>(), 1);
+/// ```
+pub struct Typed<T: 'static> {
+    init_func: fn() -> T,
+    _not_send: PhantomData<*const ()>,
+}
+
+thread_local! {
+    // One `RefManagerDataGuard<T>` per type, boxed so that it stays in place.
+    static TYPED_SLOTS: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+// The `fn() -> T` registered for each type.
+static INITIALIZERS: Mutex<Option<HashMap<TypeId, Box<dyn Any + Send>>>> = Mutex::new(None);
+
+/// Returns the handle of the current thread's value of type `T`, which is created with
+/// `T::default()` unless an initializer has been registered for `T`.
+pub fn typed<T: Default + 'static>() -> Typed<T> {
+    Typed::new(T::default)
+}
+
+/// Makes `init_func` the initializer of the values of type `T` for every thread, in place of
+/// the initializer of the handles. Values that already exist are left untouched.
+pub fn register_typed_initializer<T: 'static>(init_func: fn() -> T) {
+    let mut initializers = INITIALIZERS.lock().unwrap_or_else(|err| err.into_inner());
+    initializers
+        .get_or_insert_with(HashMap::new)
+        .insert(TypeId::of::<T>(), Box::new(init_func));
+}
+
+fn registered_initializer<T: 'static>() -> Option<fn() -> T> {
+    let initializers = INITIALIZERS.lock().unwrap_or_else(|err| err.into_inner());
+    initializers
+        .as_ref()?
+        .get(&TypeId::of::<T>())
+        .and_then(|init_func| init_func.downcast_ref::<fn() -> T>())
+        .copied()
+}
+
+impl<T: 'static> Typed<T> {
+    /// Returns the handle of the current thread's value of type `T`, which is created with
+    /// `init_func` unless an initializer has been registered for `T`.
+    pub fn new(init_func: fn() -> T) -> Self {
+        Typed {
+            init_func,
+            _not_send: PhantomData,
+        }
+    }
+
+    // Returns the current thread's guard for `T`, creating it on first use.
+    fn get_guard(&self) -> &RefManagerDataGuard<T> {
+        let ptr_guard = TYPED_SLOTS.with(|slots| {
+            let mut slots = slots.borrow_mut();
+            let guard = slots
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(RefManagerDataGuard::<T>::INIT_SELF));
+            guard.downcast_ref::<RefManagerDataGuard<T>>().unwrap() as *const _
+        });
+        // the box is only dropped when the thread exits
+        unsafe { &*ptr_guard }
+    }
+
+    fn init_value(&self) -> T {
+        registered_initializer::<T>().unwrap_or(self.init_func)()
+    }
+
+    fn get_initialized_guard(&self) -> &RefManagerDataGuard<T> {
+        let guard = self.get_guard();
+        if !guard.is_initialized() {
+            // the initializer may use other typed values
+            let _ = guard.set(self.init_value());
+        }
+        guard
+    }
+}
+
+impl<T: 'static> RefThreadLocal<T> for Typed<T> {
+    fn initialize(&self) -> Result<(), ()> {
+        let guard = self.get_guard();
+        if guard.is_initialized() {
+            Err(())
+        } else {
+            guard.set(self.init_value()).map_err(|_| ())
+        }
+    }
+
+    fn destroy(&self) -> Result<(), ()> {
+        self.get_guard().destroy()
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.get_guard().is_initialized()
+    }
+
+    fn generation(&self) -> usize {
+        self.get_guard().generation()
+    }
+
+    fn borrow<'a>(&self) -> Ref<'a, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    fn borrow_mut<'a>(&self) -> RefMut<'a, T> {
+        self.try_borrow_mut()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn try_borrow<'a>(&self) -> Result<Ref<'a, T>, BorrowError> {
+        self.get_initialized_guard().try_borrow()
+    }
+
+    fn try_borrow_mut<'a>(&self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        self.get_initialized_guard().try_borrow_mut()
+    }
+}
+
+impl<T: 'static> SetThreadLocal<T> for Typed<T> {
+    fn set(&self, value: T) -> Result<(), T> {
+        self.get_guard().set(value)
+    }
+
+    fn take(&self) -> Option<T> {
+        self.get_guard().take()
+    }
+}
+
+impl<T: 'static> FreezeThreadLocal<T> for Typed<T> {
+    fn freeze(&self) -> Result<(), ()> {
+        self.get_initialized_guard().freeze()
+    }
+
+    unsafe fn unfreeze(&self) {
+        self.get_guard().unfreeze()
+    }
+
+    fn is_frozen(&self) -> bool {
+        self.get_guard().is_frozen()
+    }
+
+    fn with_frozen<R, F: FnOnce(FrozenRef<'_, T>) -> R>(&self, f: F) -> R {
+        self.get_initialized_guard()
+            .with_frozen(f)
+            .expect("already mutably borrowed")
+    }
+}
+
+impl<T: 'static> Clone for Typed<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: 'static> Copy for Typed<T> {}
+
+impl<T: 'static> Debug for Typed<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_tuple("Typed")
+            .field(&any::type_name::<T>())
+            .finish()
+    }
+}
@@ -0,0 +1,61 @@
+#[macro_use]
+extern crate ref_thread_local;
+
+#[cfg(any(unix, windows))]
+mod at_exit {
+    use ref_thread_local::{shutdown_at_exit, FreezeThreadLocal, RefThreadLocal};
+    use std::env;
+    use std::io::Write;
+    use std::process::Command;
+
+    struct Logger;
+
+    impl Drop for Logger {
+        fn drop(&mut self) {
+            let _ = writeln!(std::io::stdout(), "dropped at exit");
+        }
+    }
+
+    ref_thread_local! {
+        static managed LOGGER: Logger = Logger;
+        static managed BUSY: u32 = 0;
+    }
+
+    const CHILD: &str = "REF_THREAD_LOCAL_AT_EXIT_CHILD";
+
+    // Returns from `main()` with one value to destroy and one that cannot be.
+    fn child() {
+        assert_eq!(shutdown_at_exit(), Ok(()));
+        assert_eq!(shutdown_at_exit(), Ok(()));
+        LOGGER.initialize().unwrap();
+        BUSY.freeze().unwrap();
+    }
+
+    pub fn run() {
+        if env::var_os(CHILD).is_some() {
+            return child();
+        }
+        let output = Command::new(env::current_exe().unwrap())
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        // glibc runs the thread-local destructors of the main thread before the `atexit()`
+        // handlers, in which case nothing is left for `shutdown()` to report
+        assert_eq!(stdout, "dropped at exit\n");
+        assert!(
+            stderr.is_empty()
+                || stderr == "ref_thread_local: still borrowed or frozen at exit: BUSY\n",
+            "{}",
+            stderr
+        );
+    }
+}
+
+// Runs without the test harness, as the child process has to return from `main()`.
+fn main() {
+    #[cfg(any(unix, windows))]
+    at_exit::run();
+}
@@ -0,0 +1,52 @@
+#[macro_use]
+extern crate ref_thread_local;
+
+#[cfg(unix)]
+mod fork {
+    use ref_thread_local::RefThreadLocal;
+    use std::os::raw::c_int;
+    use std::panic;
+
+    extern "C" {
+        fn fork() -> c_int;
+        fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+        fn _exit(status: c_int) -> !;
+    }
+
+    ref_thread_local! {
+        static managed(reset_on_fork) CONNECTION: Vec<u32> = Vec::new();
+        static managed KEPT: Vec<u32> = Vec::new();
+    }
+
+    // Runs `f` in a child process and returns whether it succeeded.
+    fn in_child<F: FnOnce()>(f: F) -> bool {
+        match unsafe { fork() } {
+            -1 => panic!("fork failed"),
+            0 => {
+                let ok = panic::catch_unwind(panic::AssertUnwindSafe(f)).is_ok();
+                unsafe { _exit(if ok { 0 } else { 1 }) }
+            }
+            pid => {
+                let mut status = 0;
+                assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+                status == 0
+            }
+        }
+    }
+
+    pub fn reset_in_child() {
+        CONNECTION.borrow_mut().push(1);
+        KEPT.borrow_mut().push(1);
+        assert!(in_child(|| {
+            assert!(CONNECTION.borrow().is_empty());
+            assert_eq!(*KEPT.borrow(), vec![1]);
+        }));
+        assert_eq!(*CONNECTION.borrow(), vec![1]);
+    }
+}
+
+// Runs without the test harness, whose threads the child process would not have.
+fn main() {
+    #[cfg(unix)]
+    fork::reset_in_child();
+}
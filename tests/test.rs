@@ -1,6 +1,14 @@
+// These fire in the initializers of the baseline statics below, which are kept as written.
+// Attributes on a macro invocation are ignored, so they cannot be scoped any closer.
+#![allow(
+    unknown_lints,
+    unnecessary_transmutes,
+    unused_braces,
+    clippy::unused_unit
+)]
 #[macro_use]
 extern crate ref_thread_local;
-use ref_thread_local::RefThreadLocal;
+use ref_thread_local::{FreezeThreadLocal, FrozenRef, RefThreadLocal};
 use std::collections::HashMap;
 
 ref_thread_local! {
@@ -46,6 +54,7 @@ fn times_two(n: u32) -> u32 {
 }
 
 #[test]
+#[allow(clippy::legacy_numeric_constants)]
 fn test_basic() {
     assert_eq!(&**STRING.borrow(), "hello");
     assert_eq!(*NUMBER.borrow(), 6);
@@ -82,6 +91,7 @@ fn test_repeat() {
 }
 
 #[test]
+#[allow(clippy::ptr_eq)]
 fn test_meta() {
     // this would not compile if STRING were not marked #[derive(Copy, Clone)]
     let copy_of_string = STRING;
@@ -165,8 +175,10 @@ fn item_name_shadowing() {
 
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::SeqCst;
+#[allow(deprecated)]
 use std::sync::atomic::ATOMIC_BOOL_INIT;
 
+#[allow(deprecated)]
 static PRE_INIT_FLAG: AtomicBool = ATOMIC_BOOL_INIT;
 
 ref_thread_local! {
@@ -177,6 +189,7 @@ ref_thread_local! {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn pre_init() {
     assert_eq!(PRE_INIT_FLAG.load(SeqCst), false);
     let _ = PRE_INIT.initialize();
@@ -240,3 +253,1776 @@ fn refmut_map_split() {
     }
     assert_eq!(&*ARR.borrow(), &[2, 3, 4, 5, 10, 12, 14, 16]);
 }
+
+#[test]
+fn freeze() {
+    ref_thread_local! {
+        static managed TABLE: Vec<u32> = vec![1, 2, 3];
+    }
+    let before = TABLE.borrow();
+    TABLE.freeze().expect("failed to freeze");
+    assert!(TABLE.is_frozen());
+    assert!(TABLE.freeze().is_err());
+    {
+        let a = TABLE.borrow();
+        let b = TABLE.borrow();
+        assert_eq!(&*a, &*b);
+    }
+    drop(before);
+    assert!(TABLE.try_borrow_mut().is_err());
+    assert_eq!(&*TABLE.borrow(), &[1, 2, 3]);
+}
+
+#[test]
+fn with_frozen() {
+    ref_thread_local! {
+        static managed TABLE: Vec<u32> = vec![1, 2, 3];
+    }
+    let sum = TABLE.with_frozen(|table| {
+        assert!(TABLE.is_frozen());
+        assert!(TABLE.try_borrow_mut().is_err());
+        let nested = TABLE.with_frozen(|table| table.len());
+        assert_eq!(nested, 3);
+        let first = FrozenRef::map(table, |table| &table[0]);
+        table.iter().sum::<u32>() + *first
+    });
+    assert_eq!(sum, 7);
+    assert!(!TABLE.is_frozen());
+    TABLE.borrow_mut().push(4);
+    assert_eq!(&*TABLE.borrow(), &[1, 2, 3, 4]);
+    assert_eq!(TABLE.destroy(), Ok(()));
+}
+
+#[test]
+fn destroyed_after_with_frozen() {
+    ref_thread_local! {
+        static managed TABLE: Vec<u32> = vec![1, 2, 3];
+    }
+    std::thread::spawn(|| {
+        let busy = TABLE.with_frozen(|_| ref_thread_local::destroy_all_on_current_thread());
+        assert_eq!(busy, Err(vec!["TABLE"]));
+        assert_eq!(ref_thread_local::shutdown(), Ok(()));
+        assert!(!TABLE.is_initialized());
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+#[should_panic(expected = "cannot destroy a frozen value")]
+fn destroy_in_with_frozen() {
+    ref_thread_local! {
+        static managed VALUE: u32 = 0;
+    }
+    VALUE.with_frozen(|_| {
+        let _ = VALUE.destroy();
+    });
+}
+
+#[test]
+fn freeze_while_mutably_borrowed() {
+    ref_thread_local! {
+        static managed VALUE: u32 = 0;
+    }
+    let guard = VALUE.borrow_mut();
+    assert!(VALUE.freeze().is_err());
+    drop(guard);
+    assert!(VALUE.freeze().is_ok());
+}
+
+#[test]
+fn unfreeze() {
+    ref_thread_local! {
+        static managed TABLE: Vec<u32> = vec![1, 2, 3];
+    }
+    TABLE.freeze().unwrap();
+    assert_eq!(TABLE.borrow().len(), 3);
+    unsafe { TABLE.unfreeze() };
+    assert!(!TABLE.is_frozen());
+    TABLE.borrow_mut().push(4);
+    TABLE.with_frozen(|_| {
+        unsafe { TABLE.unfreeze() };
+        assert!(TABLE.is_frozen());
+    });
+    assert_eq!(TABLE.destroy(), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "cannot destroy a frozen value")]
+fn destroy_frozen() {
+    ref_thread_local! {
+        static managed VALUE: u32 = 0;
+    }
+    VALUE.freeze().unwrap();
+    let _ = VALUE.destroy();
+}
+
+#[test]
+fn frozen_leaked_on_thread_exit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Table;
+    impl Drop for Table {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    ref_thread_local! {
+        static managed TABLE: Table = Table;
+    }
+    std::thread::spawn(|| {
+        TABLE.with_frozen(|_| ());
+    })
+    .join()
+    .unwrap();
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    std::thread::spawn(|| {
+        TABLE.freeze().unwrap();
+    })
+    .join()
+    .unwrap();
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    std::thread::spawn(|| {
+        TABLE.freeze().unwrap();
+        unsafe { TABLE.unfreeze() };
+    })
+    .join()
+    .unwrap();
+    assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+}
+
+mod cell {
+    use ref_thread_local::CellThreadLocal;
+    ref_thread_local! {
+        static cell COUNTER: u32 = 10;
+        pub static cell FLAG: bool = false;
+    }
+
+    #[test]
+    fn get_set() {
+        assert!(!COUNTER.is_initialized());
+        assert_eq!(COUNTER.get(), 10);
+        assert!(COUNTER.is_initialized());
+        COUNTER.set(20);
+        assert_eq!(COUNTER.replace(30), 20);
+        assert_eq!(COUNTER.update(|x| x + 1), 31);
+        assert_eq!(COUNTER.get(), 31);
+        assert!(!FLAG.replace(true));
+        assert!(FLAG.get());
+    }
+
+    #[test]
+    fn lifecycle() {
+        assert!(COUNTER.initialize().is_ok());
+        COUNTER.set(1);
+        assert!(COUNTER.destroy().is_ok());
+        assert!(COUNTER.destroy().is_err());
+        assert_eq!(COUNTER.get(), 10);
+    }
+
+    #[test]
+    fn thread_local() {
+        COUNTER.set(42);
+        std::thread::spawn(|| assert_eq!(COUNTER.get(), 10))
+            .join()
+            .unwrap();
+        assert_eq!(COUNTER.get(), 42);
+    }
+}
+
+mod once {
+    use ref_thread_local::{RefThreadLocal, SetThreadLocal};
+    ref_thread_local! {
+        static once WORKER_ID: usize;
+    }
+
+    #[test]
+    fn set_once() {
+        assert!(!WORKER_ID.is_initialized());
+        assert!(WORKER_ID.try_borrow().unwrap_err().is_unset());
+        assert!(WORKER_ID.try_borrow_mut().unwrap_err().is_unset());
+        assert!(WORKER_ID.initialize().is_err());
+        assert_eq!(WORKER_ID.set(3), Ok(()));
+        assert_eq!(WORKER_ID.set(4), Err(4));
+        assert_eq!(*WORKER_ID.borrow(), 3);
+        *WORKER_ID.borrow_mut() += 1;
+        assert_eq!(*WORKER_ID.borrow(), 4);
+        assert!(WORKER_ID.destroy().is_ok());
+        assert_eq!(WORKER_ID.set(5), Ok(()));
+        assert_eq!(*WORKER_ID.borrow(), 5);
+    }
+
+    #[test]
+    fn per_thread() {
+        WORKER_ID.set(1).unwrap();
+        std::thread::spawn(|| assert!(!WORKER_ID.is_initialized()))
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "`WORKER_ID` is not set on this thread")]
+    fn borrow_unset() {
+        let _ = WORKER_ID.borrow();
+    }
+}
+
+mod thread_context {
+    use ref_thread_local::{RefThreadLocal, ThreadContext};
+    use std::thread;
+
+    ref_thread_local! {
+        static managed(context) SHARD: usize = |ctx| ctx.index() % 4;
+        static managed(context) PREFIX: String = |ctx| format!("[{}]", ctx.name().unwrap_or("?"));
+        static managed(context) ID: thread::ThreadId = |ctx| ctx.id();
+        static managed(context) IGNORED: u32 = |_| 7;
+        static managed INCREMENT: fn(u32) -> u32 = |x| x + 1;
+    }
+
+    #[test]
+    fn closure_valued_static() {
+        assert_eq!((*INCREMENT.borrow())(1), 2);
+    }
+
+    #[test]
+    fn initializer_sees_thread() {
+        assert_eq!(*ID.borrow(), thread::current().id());
+        assert_eq!(*SHARD.borrow(), ThreadContext::current().index() % 4);
+        assert_eq!(*IGNORED.borrow(), 7);
+        let prefix = thread::Builder::new()
+            .name("worker".to_string())
+            .spawn(|| PREFIX.borrow().clone())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(prefix, "[worker]");
+    }
+
+    #[test]
+    fn live_threads_have_distinct_indices() {
+        let here = ThreadContext::current().index();
+        assert_eq!(ThreadContext::current().index(), here);
+        let there = thread::spawn(|| ThreadContext::current().index())
+            .join()
+            .unwrap();
+        assert_ne!(here, there);
+    }
+
+    #[test]
+    fn initialized_during_teardown() {
+        struct InitOnDrop;
+        impl Drop for InitOnDrop {
+            fn drop(&mut self) {
+                assert_eq!(*IGNORED.borrow(), 7);
+            }
+        }
+        thread_local! {
+            static INIT_ON_DROP: InitOnDrop = const { InitOnDrop };
+        }
+        thread::spawn(|| {
+            // destructors run in reverse registration order, so the thread's index is
+            // released before `INIT_ON_DROP` is dropped
+            INIT_ON_DROP.with(|_| ());
+            let _ = ThreadContext::current();
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+mod closure_init {
+    use ref_thread_local::{RefManager, RefManagerDataGuard, RefThreadLocal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    thread_local! {
+        static DATA: RefManagerDataGuard<Vec<usize>> = const { RefManagerDataGuard::INIT_SELF };
+    }
+
+    // A facade that only exposes the `RefThreadLocal` surface.
+    fn facade(manager: &dyn RefThreadLocal<Vec<usize>>) -> usize {
+        manager.borrow_mut().push(0);
+        manager.borrow().len()
+    }
+
+    #[test]
+    fn captures_state() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let manager = RefManager::from_fn(&DATA, move || {
+            vec![counter.fetch_add(1, Ordering::SeqCst); 2]
+        });
+        assert_eq!(facade(&manager), 3);
+        assert_eq!(facade(&manager), 4);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        manager.destroy().unwrap();
+        assert_eq!(&*manager.borrow(), &[1, 1]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
+
+mod per_thread {
+    use ref_thread_local::PerThread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Tracked(Arc<AtomicUsize>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct Owner {
+        scratch: PerThread<Vec<u32>>,
+    }
+
+    #[test]
+    fn per_instance() {
+        let a = Owner {
+            scratch: PerThread::new(|| vec![1]),
+        };
+        let b = Owner {
+            scratch: PerThread::new(|| vec![2]),
+        };
+        a.scratch.borrow_mut().push(10);
+        assert_eq!(&*a.scratch.borrow(), &[1, 10]);
+        assert_eq!(&*b.scratch.borrow(), &[2]);
+        let _r = a.scratch.borrow();
+        assert!(a.scratch.try_borrow_mut().is_err());
+        assert!(b.scratch.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn per_thread_values() {
+        let shared = Arc::new(PerThread::new(|| 0u32));
+        *shared.borrow_mut() = 5;
+        let handles: Vec<_> = (1..4)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    assert_eq!(*shared.borrow(), 0);
+                    *shared.borrow_mut() = i;
+                    *shared.borrow()
+                })
+            })
+            .collect();
+        let values: Vec<u32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(values, vec![1, 2, 3]);
+        assert_eq!(*shared.borrow(), 5);
+    }
+
+    #[test]
+    fn dropped_on_thread_exit() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counter = drops.clone();
+        let shared = Arc::new(PerThread::new(move || Tracked(counter.clone())));
+        let other = shared.clone();
+        thread::spawn(move || {
+            other.initialize().unwrap();
+        })
+        .join()
+        .unwrap();
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        shared.initialize().unwrap();
+        shared.destroy().unwrap();
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dropped_with_owner() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counter = drops.clone();
+        let shared = Arc::new(PerThread::new(move || Tracked(counter.clone())));
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let (exit_tx, exit_rx) = std::sync::mpsc::channel::<()>();
+        let other = shared.clone();
+        let handle = thread::spawn(move || {
+            other.initialize().unwrap();
+            drop(other);
+            ready_tx.send(()).unwrap();
+            exit_rx.recv().unwrap();
+        });
+        ready_rx.recv().unwrap();
+        shared.initialize().unwrap();
+        drop(shared);
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        exit_tx.send(()).unwrap();
+        handle.join().unwrap();
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn frozen_dropped_with_owner() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counter = drops.clone();
+        let mut local = PerThread::new(move || Tracked(counter.clone()));
+        local.freeze().unwrap();
+        assert!(local.is_frozen());
+        drop(local.borrow());
+        local.unfreeze();
+        assert!(local.try_borrow_mut().is_ok());
+        local.freeze().unwrap();
+        drop(local);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+}
+
+mod shared {
+    use ref_thread_local::{FreezeThreadLocal, RefThreadLocal, SharedThreadLocal, StaticInfo};
+    use std::cell::Cell;
+    use std::panic;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    ref_thread_local! {
+        static managed(shared) HITS: u32 = 0;
+        pub static managed(context, shared) NAMES: Vec<String> = |ctx| vec![ctx.index().to_string()];
+    }
+
+    #[test]
+    fn collect_from_all_threads() {
+        let barrier = Arc::new(Barrier::new(4));
+        let handles: Vec<_> = (1..4)
+            .map(|i| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    *HITS.borrow_mut() = i;
+                    barrier.wait(); // values are set
+                    barrier.wait(); // values are collected
+                    thread::current().id()
+                })
+            })
+            .collect();
+        barrier.wait();
+        let mut values: Vec<u32> = HITS.collect().into_iter().map(|(_, v)| v).collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 3]);
+        barrier.wait();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(HITS.collect().is_empty());
+        *HITS.borrow_mut() = 7;
+        let mut seen = Vec::new();
+        HITS.for_each_thread(|thread_id, value| seen.push((thread_id, *value)));
+        assert_eq!(seen, vec![(thread::current().id(), 7)]);
+        HITS.destroy().unwrap();
+        assert!(HITS.collect().is_empty());
+        assert!(HITS.freeze().is_err());
+    }
+
+    #[test]
+    fn visit_waits_for_borrow_mut() {
+        let (borrowed_tx, borrowed_rx) = channel();
+        let (done_tx, done_rx) = channel::<()>();
+        let handle = thread::spawn(move || {
+            let mut names = NAMES.borrow_mut();
+            borrowed_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            names.push("written".to_string());
+            drop(names);
+            done_rx.recv().unwrap();
+        });
+        borrowed_rx.recv().unwrap();
+        let mut lens = Vec::new();
+        NAMES.for_each_thread(|_, names| lens.push(names.len()));
+        assert_eq!(lens, vec![2]);
+        done_tx.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    ref_thread_local! {
+        static managed(shared) COUNTER: Cell<u32> = Cell::new(0);
+        static managed(shared) FROZEN: u32 = 0;
+    }
+
+    #[test]
+    fn visit_waits_for_with_frozen() {
+        let (frozen_tx, frozen_rx) = channel();
+        let (done_tx, done_rx) = channel::<()>();
+        let handle = thread::spawn(move || {
+            COUNTER.with_frozen(|counter| {
+                assert!(COUNTER.is_borrowed());
+                frozen_tx.send(()).unwrap();
+                thread::sleep(Duration::from_millis(50));
+                counter.set(1);
+            });
+            done_rx.recv().unwrap();
+        });
+        frozen_rx.recv().unwrap();
+        let mut values = Vec::new();
+        COUNTER.for_each_thread(|_, counter| values.push(counter.get()));
+        assert_eq!(values, vec![1]);
+        done_tx.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn destroy_in_with_frozen() {
+        thread::spawn(|| {
+            *FROZEN.borrow_mut() = 5;
+            let destroyed = panic::catch_unwind(|| {
+                FROZEN.with_frozen(|_| {
+                    let _ = FROZEN.destroy();
+                })
+            });
+            assert!(destroyed.is_err());
+            let mut values = Vec::new();
+            FROZEN.for_each_thread(|_, value| values.push(*value));
+            assert_eq!(values, vec![5]);
+        })
+        .join()
+        .unwrap();
+    }
+
+    ref_thread_local! {
+        static managed(shared) VISITED: u32 = 0;
+    }
+
+    #[test]
+    fn owner_joins_exiting_thread_during_visit() {
+        thread::spawn(|| {
+            let visited = VISITED.borrow();
+            let (ready_tx, ready_rx) = channel();
+            let (exit_tx, exit_rx) = channel::<()>();
+            let worker = thread::spawn(move || {
+                *VISITED.borrow_mut() += 1;
+                ready_tx.send(()).unwrap();
+                exit_rx.recv().unwrap();
+            });
+            ready_rx.recv().unwrap();
+            // waits for `visited` to be dropped, which only happens once the worker exits
+            let visitor = thread::spawn(|| {
+                let mut count = 0;
+                VISITED.for_each_thread(|_, _| count += 1);
+                count
+            });
+            thread::sleep(Duration::from_millis(50));
+            exit_tx.send(()).unwrap();
+            let (joined_tx, joined_rx) = channel();
+            thread::spawn(move || joined_tx.send(worker.join().is_ok()).unwrap());
+            let joined = joined_rx.recv_timeout(Duration::from_secs(10));
+            drop(visited);
+            assert_eq!(joined, Ok(true));
+            assert_eq!(visitor.join().unwrap(), 1);
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+mod merge {
+    use ref_thread_local::{MergeThreadLocal, RefThreadLocal};
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[derive(Default)]
+    struct Stats {
+        requests: u32,
+    }
+
+    static GLOBAL_STATS: Mutex<Stats> = Mutex::new(Stats { requests: 0 });
+
+    fn merge_into(global: &'static Mutex<Stats>) -> impl Fn(Stats) {
+        move |local| global.lock().unwrap().requests += local.requests
+    }
+
+    static GLOBAL_TOTAL: Mutex<u64> = Mutex::new(0);
+
+    fn add_to_total(local: u64) {
+        *GLOBAL_TOTAL.lock().unwrap() += local;
+    }
+
+    ref_thread_local! {
+        static managed STATS: Stats = Stats::default() => merge_into(&GLOBAL_STATS);
+        static managed(context) TOTAL: u64 = |_| 0 => add_to_total;
+    }
+
+    #[test]
+    fn merged_on_exit_destroy_and_flush() {
+        let handles: Vec<_> = (0..3)
+            .map(|_| thread::spawn(|| STATS.borrow_mut().requests += 2))
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(GLOBAL_STATS.lock().unwrap().requests, 6);
+
+        STATS.borrow_mut().requests += 1;
+        STATS.flush().unwrap();
+        assert_eq!(GLOBAL_STATS.lock().unwrap().requests, 7);
+        assert_eq!(STATS.borrow().requests, 0);
+
+        STATS.borrow_mut().requests += 10;
+        STATS.destroy().unwrap();
+        assert_eq!(GLOBAL_STATS.lock().unwrap().requests, 17);
+        assert!(STATS.flush().is_err());
+    }
+
+    #[test]
+    fn merge_function_path() {
+        thread::spawn(|| *TOTAL.borrow_mut() += 5).join().unwrap();
+        assert_eq!(*GLOBAL_TOTAL.lock().unwrap(), 5);
+    }
+}
+
+mod context {
+    use ref_thread_local::{spawn_with_context, Context, RefThreadLocal, SetThreadLocal};
+    ref_thread_local! {
+        static managed REQUEST_ID: u64 = 0;
+        static managed USER: String = String::from("anonymous");
+        static once DEADLINE: u32;
+    }
+
+    #[test]
+    fn spawn() {
+        *REQUEST_ID.borrow_mut() = 7;
+        *USER.borrow_mut() = String::from("alice");
+        DEADLINE.set(30).unwrap();
+        let handle = spawn_with_context(&[&REQUEST_ID, &USER, &DEADLINE], || {
+            *REQUEST_ID.borrow_mut() += 1;
+            (
+                *REQUEST_ID.borrow(),
+                USER.borrow().clone(),
+                *DEADLINE.borrow(),
+            )
+        });
+        assert_eq!(handle.join().unwrap(), (8, String::from("alice"), 30));
+        assert_eq!(*REQUEST_ID.borrow(), 7);
+    }
+
+    #[test]
+    fn attach_restores() {
+        let context = std::thread::spawn(|| {
+            *REQUEST_ID.borrow_mut() = 1;
+            Context::capture(&[&REQUEST_ID, &USER, &DEADLINE])
+        })
+        .join()
+        .unwrap();
+        *REQUEST_ID.borrow_mut() = 2;
+        {
+            let _outer = context.clone().attach();
+            assert_eq!(*REQUEST_ID.borrow(), 1);
+            // Uninitialized statics are not captured.
+            assert_eq!(*USER.borrow(), "anonymous");
+            assert!(!DEADLINE.is_initialized());
+            *REQUEST_ID.borrow_mut() = 3;
+            {
+                let _inner = context.attach();
+                assert_eq!(*REQUEST_ID.borrow(), 1);
+            }
+            assert_eq!(*REQUEST_ID.borrow(), 3);
+        }
+        assert_eq!(*REQUEST_ID.borrow(), 2);
+    }
+
+    #[test]
+    fn restore_on_panic() {
+        *REQUEST_ID.borrow_mut() = 5;
+        let context = std::thread::spawn(|| {
+            REQUEST_ID.initialize().unwrap();
+            Context::capture(&[&REQUEST_ID])
+        })
+        .join()
+        .unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _guard = context.attach();
+            assert_eq!(*REQUEST_ID.borrow(), 0);
+            panic!("request failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(*REQUEST_ID.borrow(), 5);
+    }
+}
+
+mod bind {
+    use ref_thread_local::{RefThreadLocal, SetThreadLocal, ShareThreadLocal};
+    ref_thread_local! {
+        static managed VERBOSITY: u8 = 1;
+    }
+
+    #[test]
+    fn scoped_set_nests() {
+        assert_eq!(*VERBOSITY.borrow(), 1);
+        let result = VERBOSITY.scoped_set(2, || {
+            assert_eq!(*VERBOSITY.borrow(), 2);
+            VERBOSITY.scoped_set(3, || *VERBOSITY.borrow()) + *VERBOSITY.borrow()
+        });
+        assert_eq!(result, 5);
+        assert_eq!(*VERBOSITY.borrow(), 1);
+    }
+
+    #[test]
+    fn bind_restores_uninitialized() {
+        assert!(!VERBOSITY.is_initialized());
+        {
+            let _guard = VERBOSITY.bind(4);
+            *VERBOSITY.borrow_mut() += 1;
+            assert_eq!(*VERBOSITY.borrow(), 5);
+        }
+        assert!(!VERBOSITY.is_initialized());
+    }
+
+    #[test]
+    fn restore_on_panic() {
+        *VERBOSITY.borrow_mut() = 7;
+        let result = std::panic::catch_unwind(|| {
+            VERBOSITY.scoped_set(8, || panic!("request failed"));
+        });
+        assert!(result.is_err());
+        assert_eq!(*VERBOSITY.borrow(), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot move out of a shared value")]
+    fn bind_shared() {
+        let _shared = VERBOSITY.share();
+        let _guard = VERBOSITY.bind(2);
+    }
+}
+
+mod scoped {
+    use ref_thread_local::ScopedThreadLocal;
+    ref_thread_local! {
+        static scoped LOG_PREFIX: str;
+        static scoped COUNTER: std::cell::Cell<u32>;
+    }
+
+    fn log(message: &str) -> String {
+        LOG_PREFIX
+            .with(|prefix| format!("{}: {}", prefix, message))
+            .unwrap_or_else(|_| message.to_string())
+    }
+
+    #[test]
+    fn set_and_with() {
+        assert!(!LOG_PREFIX.is_set());
+        assert!(LOG_PREFIX.with(|_| ()).unwrap_err().is_unset());
+        let request = format!("request {}", 1);
+        LOG_PREFIX.set(&request, || {
+            assert_eq!(log("start"), "request 1: start");
+            LOG_PREFIX.set("nested", || assert_eq!(log("step"), "nested: step"));
+            assert_eq!(log("end"), "request 1: end");
+        });
+        assert_eq!(log("idle"), "idle");
+    }
+
+    #[test]
+    fn per_thread_and_panic() {
+        let counter = std::cell::Cell::new(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            COUNTER.set(&counter, || {
+                COUNTER.with(|c| c.set(c.get() + 1)).unwrap();
+                std::thread::spawn(|| assert!(!COUNTER.is_set()))
+                    .join()
+                    .unwrap();
+                panic!("callback failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(counter.get(), 1);
+        assert!(!COUNTER.is_set());
+    }
+}
+
+mod group {
+    use ref_thread_local::{self, RefThreadLocal, SetThreadLocal, StaticStatus};
+    ref_thread_local! {
+        static group net;
+        static group stats;
+        static group warm_up;
+        /// Connections of the `net` subsystem.
+        #[group(net)]
+        static managed CONNECTIONS: Vec<u32> = vec![1, 2];
+        #[group(net, stats)]
+        static managed BYTES_SENT: u64 = 0;
+        #[group(net)]
+        static once PEER: String;
+        static managed UNGROUPED: u8 = 0;
+        #[group(warm_up)]
+        static managed COLD: u32 = 1;
+    }
+
+    fn status(name: &'static str, initialized: bool, borrowed: bool) -> StaticStatus {
+        StaticStatus {
+            name,
+            initialized,
+            frozen: false,
+            borrowed,
+        }
+    }
+
+    #[test]
+    fn reset_and_report() {
+        CONNECTIONS.borrow_mut().push(3);
+        *BYTES_SENT.borrow_mut() = 10;
+        PEER.set(String::from("localhost")).unwrap();
+        *UNGROUPED.borrow_mut() = 1;
+
+        let report = net.report();
+        assert!(report.contains(&status("CONNECTIONS", true, false)));
+        assert!(report.contains(&status("PEER", true, false)));
+        assert!(!report.iter().any(|status| status.name == "UNGROUPED"));
+
+        let bytes = BYTES_SENT.borrow();
+        assert_eq!(net.reset_all(), Err(vec!["BYTES_SENT"]));
+        assert!(net.report().contains(&status("BYTES_SENT", true, true)));
+        drop(bytes);
+        assert_eq!(*CONNECTIONS.borrow(), vec![1, 2]);
+        assert!(!PEER.is_initialized());
+        assert_eq!(*BYTES_SENT.borrow(), 10);
+        assert_eq!(*UNGROUPED.borrow(), 1);
+
+        assert_eq!(stats.destroy_all(), Ok(()));
+        assert!(!BYTES_SENT.is_initialized());
+        assert!(CONNECTIONS.is_initialized());
+    }
+
+    #[test]
+    fn initialize_all() {
+        CONNECTIONS.initialize().unwrap();
+        std::thread::spawn(|| {
+            net.initialize_all();
+            assert!(CONNECTIONS.is_initialized());
+            assert!(!PEER.is_initialized());
+            assert!(!UNGROUPED.is_initialized());
+        })
+        .join()
+        .unwrap();
+    }
+
+    // `COLD` is not used by any other test, so no thread has initialized it before.
+    #[test]
+    fn initialize_all_before_first_use() {
+        assert_eq!(warm_up.report(), vec![status("COLD", false, false)]);
+        warm_up.initialize_all();
+        assert!(COLD.is_initialized());
+    }
+
+    #[test]
+    fn destroy_all_on_current_thread() {
+        CONNECTIONS.initialize().unwrap();
+        UNGROUPED.initialize().unwrap();
+        assert_eq!(ref_thread_local::destroy_all_on_current_thread(), Ok(()));
+        assert!(!CONNECTIONS.is_initialized());
+        assert!(!UNGROUPED.is_initialized());
+    }
+
+    // every static costs a single level of macro recursion, however many attributes it has
+    ref_thread_local! {
+        static group many;
+        /// Static number 0.
+        #[group(many)]
+        static managed MANY_00: usize = 0;
+        /// Static number 1.
+        static managed MANY_01: usize = 1;
+        /// Static number 2.
+        #[group(many)]
+        static managed MANY_02: usize = 2;
+        /// Static number 3.
+        static managed MANY_03: usize = 3;
+        /// Static number 4.
+        #[group(many)]
+        static managed MANY_04: usize = 4;
+        /// Static number 5.
+        static managed MANY_05: usize = 5;
+        /// Static number 6.
+        #[group(many)]
+        static managed MANY_06: usize = 6;
+        /// Static number 7.
+        static managed MANY_07: usize = 7;
+        /// Static number 8.
+        #[group(many)]
+        static managed MANY_08: usize = 8;
+        /// Static number 9.
+        static managed MANY_09: usize = 9;
+        /// Static number 10.
+        #[group(many)]
+        static managed MANY_10: usize = 10;
+        /// Static number 11.
+        static managed MANY_11: usize = 11;
+        /// Static number 12.
+        #[group(many)]
+        static managed MANY_12: usize = 12;
+        /// Static number 13.
+        static managed MANY_13: usize = 13;
+        /// Static number 14.
+        #[group(many)]
+        static managed MANY_14: usize = 14;
+        /// Static number 15.
+        static managed MANY_15: usize = 15;
+        /// Static number 16.
+        #[group(many)]
+        static managed MANY_16: usize = 16;
+        /// Static number 17.
+        static managed MANY_17: usize = 17;
+        /// Static number 18.
+        #[group(many)]
+        static managed MANY_18: usize = 18;
+        /// Static number 19.
+        static managed MANY_19: usize = 19;
+        /// Static number 20.
+        #[group(many)]
+        static managed MANY_20: usize = 20;
+        /// Static number 21.
+        static managed MANY_21: usize = 21;
+        /// Static number 22.
+        #[group(many)]
+        static managed MANY_22: usize = 22;
+        /// Static number 23.
+        static managed MANY_23: usize = 23;
+        /// Static number 24.
+        #[group(many)]
+        static managed MANY_24: usize = 24;
+        /// Static number 25.
+        static managed MANY_25: usize = 25;
+        /// Static number 26.
+        #[group(many)]
+        static managed MANY_26: usize = 26;
+        /// Static number 27.
+        static managed MANY_27: usize = 27;
+        /// Static number 28.
+        #[group(many)]
+        static managed MANY_28: usize = 28;
+        /// Static number 29.
+        static managed MANY_29: usize = 29;
+        /// Static number 30.
+        #[group(many)]
+        static managed MANY_30: usize = 30;
+        /// Static number 31.
+        static managed MANY_31: usize = 31;
+        /// Static number 32.
+        #[group(many)]
+        static managed MANY_32: usize = 32;
+        /// Static number 33.
+        static managed MANY_33: usize = 33;
+        /// Static number 34.
+        #[group(many)]
+        static managed MANY_34: usize = 34;
+        /// Static number 35.
+        static managed MANY_35: usize = 35;
+        /// Static number 36.
+        #[group(many)]
+        static managed MANY_36: usize = 36;
+        /// Static number 37.
+        static managed MANY_37: usize = 37;
+        /// Static number 38.
+        #[group(many)]
+        static managed MANY_38: usize = 38;
+        /// Static number 39.
+        static managed MANY_39: usize = 39;
+        /// Static number 40.
+        #[group(many)]
+        static managed MANY_40: usize = 40;
+        /// Static number 41.
+        static managed MANY_41: usize = 41;
+        /// Static number 42.
+        #[group(many)]
+        static managed MANY_42: usize = 42;
+        /// Static number 43.
+        static managed MANY_43: usize = 43;
+        /// Static number 44.
+        #[group(many)]
+        static managed MANY_44: usize = 44;
+        /// Static number 45.
+        static managed MANY_45: usize = 45;
+        /// Static number 46.
+        #[group(many)]
+        static managed MANY_46: usize = 46;
+        /// Static number 47.
+        static managed MANY_47: usize = 47;
+        /// Static number 48.
+        #[group(many)]
+        static managed MANY_48: usize = 48;
+        /// Static number 49.
+        static managed MANY_49: usize = 49;
+        /// Static number 50.
+        #[group(many)]
+        static managed MANY_50: usize = 50;
+        /// Static number 51.
+        static managed MANY_51: usize = 51;
+        /// Static number 52.
+        #[group(many)]
+        static managed MANY_52: usize = 52;
+        /// Static number 53.
+        static managed MANY_53: usize = 53;
+        /// Static number 54.
+        #[group(many)]
+        static managed MANY_54: usize = 54;
+        /// Static number 55.
+        static managed MANY_55: usize = 55;
+        /// Static number 56.
+        #[group(many)]
+        static managed MANY_56: usize = 56;
+        /// Static number 57.
+        static managed MANY_57: usize = 57;
+        /// Static number 58.
+        #[group(many)]
+        static managed MANY_58: usize = 58;
+        /// Static number 59.
+        static managed MANY_59: usize = 59;
+        /// Static number 60.
+        #[group(many)]
+        static managed MANY_60: usize = 60;
+        /// Static number 61.
+        static managed MANY_61: usize = 61;
+        /// Static number 62.
+        #[group(many)]
+        static managed MANY_62: usize = 62;
+        /// Static number 63.
+        static managed MANY_63: usize = 63;
+    }
+
+    #[test]
+    fn many_statics_in_one_invocation() {
+        std::thread::spawn(|| {
+            many.initialize_all();
+            let report = many.report();
+            assert_eq!(report.len(), 32);
+            assert!(report.iter().all(|status| status.initialized));
+            assert!(!MANY_01.is_initialized());
+            assert_eq!(*MANY_00.borrow() + *MANY_63.borrow(), 63);
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+mod managed_group {
+    use ref_thread_local::StructThreadLocal;
+    ref_thread_local! {
+        static managed group CTX {
+            buffer: Vec<u8> = Vec::with_capacity(16),
+            depth: usize = 1,
+        }
+        pub(crate) static managed group EMPTY {}
+    }
+
+    #[test]
+    fn split_borrows() {
+        assert!(!CTX.is_initialized());
+        let mut buffer = CTX.buffer.borrow_mut();
+        assert!(CTX.is_initialized());
+        let depth = CTX.depth.borrow();
+        buffer.push(*depth as u8);
+        assert!(CTX.buffer.try_borrow().is_err());
+        assert!(CTX.depth.try_borrow_mut().is_err());
+        drop(buffer);
+        drop(depth);
+        *CTX.depth.borrow_mut() += 1;
+        assert_eq!(*CTX.buffer.borrow(), vec![1]);
+        assert_eq!(*CTX.depth.borrow(), 2);
+        assert!(CTX.destroy().is_ok());
+        assert_eq!(*CTX.depth.borrow(), 1);
+        assert!(EMPTY.initialize().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot destroy before all references are dropped")]
+    fn destroy_while_borrowed() {
+        let _depth = CTX.depth.borrow();
+        let _ = CTX.destroy();
+    }
+}
+
+mod borrow_many {
+    use ref_thread_local::RefThreadLocal;
+    ref_thread_local! {
+        static managed SOURCE: Vec<u32> = vec![1, 2, 3];
+        static managed SINK: Vec<u32> = Vec::new();
+        static managed TOTAL: u32 = 0;
+        static once UNSET: u32;
+    }
+
+    #[test]
+    fn all_or_nothing() {
+        {
+            let (source, mut sink, mut total) =
+                try_borrow_many!(&SOURCE, &mut SINK, &mut TOTAL).unwrap();
+            sink.extend(source.iter());
+            *total = source.iter().sum();
+        }
+        let (sink,) = borrow_many!(&SINK);
+        assert_eq!(*sink, vec![1, 2, 3]);
+
+        let err = try_borrow_many!(&mut TOTAL, &mut SOURCE, &mut SINK).unwrap_err();
+        assert_eq!(err.index(), 2);
+        assert_eq!(err.name(), "SINK");
+        assert_eq!(
+            err.to_string(),
+            "cannot borrow `SINK` (argument 3): already borrowed"
+        );
+        drop(sink);
+        // the borrows taken before the conflict have been released
+        *TOTAL.borrow_mut() += 1;
+        SOURCE.borrow_mut().clear();
+        assert_eq!(*TOTAL.borrow(), 7);
+
+        let err = try_borrow_many!(&SOURCE, &UNSET).unwrap_err();
+        assert!(err.is_unset());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot borrow `SOURCE` (argument 2): already mutably borrowed")]
+    fn same_static_twice() {
+        let _ = borrow_many!(&mut SOURCE, &SOURCE);
+    }
+}
+
+mod epoch {
+    use ref_thread_local::{EpochThreadLocal, RefThreadLocal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    static CONFIG_VERSION: AtomicUsize = AtomicUsize::new(1);
+
+    ref_thread_local! {
+        static managed CONFIG: usize = CONFIG_VERSION.load(Ordering::SeqCst);
+        static managed CACHE: usize = 1;
+    }
+
+    #[test]
+    fn rebuild_on_all_threads() {
+        let (to_worker, from_main) = mpsc::channel::<()>();
+        let (to_main, from_worker) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            to_main.send(*CONFIG.borrow()).unwrap();
+            from_main.recv().unwrap();
+            to_main.send(*CONFIG.borrow()).unwrap();
+        });
+        assert_eq!(from_worker.recv().unwrap(), 1);
+        assert_eq!(*CONFIG.borrow(), 1);
+
+        let epoch = CONFIG.epoch();
+        CONFIG_VERSION.store(2, Ordering::SeqCst);
+        assert_eq!(CONFIG.invalidate_all_threads(), epoch + 1);
+        assert_eq!(CONFIG.epoch(), epoch + 1);
+        assert_eq!(*CONFIG.borrow(), 2);
+        to_worker.send(()).unwrap();
+        assert_eq!(from_worker.recv().unwrap(), 2);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn deferred_while_borrowed() {
+        *CACHE.borrow_mut() = 0;
+        let value = CACHE.borrow();
+        CACHE.invalidate_all_threads();
+        assert_eq!(*CACHE.borrow(), 0);
+        drop(value);
+        assert_eq!(*CACHE.borrow(), 1);
+    }
+}
+
+mod mirror {
+    use ref_thread_local::{GlobalCell, RefThreadLocal};
+    use std::sync::{mpsc, Arc};
+
+    #[derive(Debug, PartialEq)]
+    struct Config {
+        verbose: bool,
+    }
+
+    fn default_config() -> Config {
+        Config { verbose: false }
+    }
+
+    static GLOBAL_CONFIG: GlobalCell<Config> = GlobalCell::new(default_config);
+
+    ref_thread_local! {
+        static mirror CONFIG: Arc<Config> = from(&GLOBAL_CONFIG);
+    }
+
+    #[test]
+    fn publish_reaches_every_thread() {
+        let (to_worker, from_main) = mpsc::channel::<()>();
+        let (to_main, from_worker) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            to_main.send(CONFIG.borrow().verbose).unwrap();
+            from_main.recv().unwrap();
+            to_main.send(CONFIG.borrow().verbose).unwrap();
+        });
+        assert!(!from_worker.recv().unwrap());
+        let before = CONFIG.borrow().clone();
+        assert_eq!(GLOBAL_CONFIG.version(), 0);
+
+        GLOBAL_CONFIG.publish(Config { verbose: true });
+        assert_eq!(GLOBAL_CONFIG.version(), 1);
+        assert!(CONFIG.borrow().verbose);
+        assert!(Arc::ptr_eq(&CONFIG.borrow(), &GLOBAL_CONFIG.load()));
+        assert!(!before.verbose);
+        to_worker.send(()).unwrap();
+        assert!(from_worker.recv().unwrap());
+        worker.join().unwrap();
+    }
+}
+
+mod mailbox {
+    use ref_thread_local::{FreezeThreadLocal, MailboxThreadLocal, RefThreadLocal};
+    use std::sync::mpsc;
+
+    ref_thread_local! {
+        static managed(mailbox) QUEUE: Vec<u32> = Vec::new();
+        static managed(shared, mailbox) FLUSHED: bool = false;
+    }
+
+    #[test]
+    fn run_on_next_borrow() {
+        let (to_worker, from_main) = mpsc::channel::<()>();
+        let (to_main, from_worker) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            QUEUE.borrow_mut().push(1);
+            to_main.send(std::thread::current().id()).unwrap();
+            from_main.recv().unwrap();
+            QUEUE.borrow().clone()
+        });
+        let worker_id = from_worker.recv().unwrap();
+        let done = QUEUE.post(worker_id, Box::new(|queue| queue.push(2)));
+        QUEUE.post(worker_id, Box::new(|queue| queue.push(3)));
+        to_worker.send(()).unwrap();
+        assert_eq!(worker.join().unwrap(), vec![1, 2, 3]);
+        done.wait().unwrap();
+        assert!(QUEUE.borrow().is_empty());
+    }
+
+    #[test]
+    fn drain_explicitly() {
+        let id = std::thread::current().id();
+        FLUSHED.initialize().unwrap();
+        FLUSHED.post(id, Box::new(|flushed| *flushed = true));
+        assert_eq!(FLUSHED.drain_mailbox(), 1);
+        assert_eq!(FLUSHED.drain_mailbox(), 0);
+        assert!(*FLUSHED.borrow());
+    }
+
+    #[test]
+    fn deferred_while_borrowed() {
+        let id = std::thread::current().id();
+        let queue = QUEUE.borrow();
+        QUEUE.post(id, Box::new(|queue| queue.push(4)));
+        assert!(QUEUE.borrow().is_empty());
+        drop(queue);
+        assert_eq!(*QUEUE.borrow(), vec![4]);
+    }
+
+    #[test]
+    fn cancelled_on_thread_exit() {
+        let (to_main, from_worker) = mpsc::channel();
+        let (to_worker, from_main) = mpsc::channel::<()>();
+        let worker = std::thread::spawn(move || {
+            QUEUE.initialize().unwrap();
+            to_main.send(std::thread::current().id()).unwrap();
+            from_main.recv().unwrap();
+        });
+        let done = QUEUE.post(from_worker.recv().unwrap(), Box::new(|queue| queue.push(5)));
+        to_worker.send(()).unwrap();
+        worker.join().unwrap();
+        assert!(done.wait().is_err());
+    }
+
+    #[test]
+    fn cancelled_on_destroy() {
+        std::thread::spawn(|| {
+            let id = std::thread::current().id();
+            QUEUE.initialize().unwrap();
+            let done = QUEUE.post(id, Box::new(|queue| queue.push(8)));
+            QUEUE.destroy().unwrap();
+            assert!(done.wait().is_err());
+            let done = QUEUE.post(id, Box::new(|queue| queue.push(9)));
+            assert!(done.wait().is_err());
+
+            QUEUE.initialize().unwrap();
+            let done = QUEUE.post(id, Box::new(|queue| queue.push(10)));
+            assert_eq!(QUEUE.drain_mailbox(), 1);
+            assert!(done.wait().is_ok());
+            assert_eq!(*QUEUE.borrow(), vec![10]);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn dropped_without_owner() {
+        let worker = std::thread::spawn(|| QUEUE.initialize().unwrap());
+        let exited = worker.thread().id();
+        worker.join().unwrap();
+        let done = QUEUE.post(exited, Box::new(|queue| queue.push(6)));
+        assert!(done.wait().is_err());
+
+        let (to_main, from_worker) = mpsc::channel();
+        let (to_worker, from_main) = mpsc::channel::<()>();
+        let idle = std::thread::spawn(move || {
+            to_main.send(std::thread::current().id()).unwrap();
+            from_main.recv().unwrap();
+        });
+        let idle_id = from_worker.recv().unwrap();
+        let done = QUEUE.post(idle_id, Box::new(|queue| queue.push(7)));
+        assert!(done.wait().is_err());
+        to_worker.send(()).unwrap();
+        idle.join().unwrap();
+    }
+
+    ref_thread_local! {
+        static managed(mailbox) TABLE: u32 = 0;
+    }
+
+    #[test]
+    fn frozen() {
+        let current_id = std::thread::current().id();
+        let done = TABLE.with_frozen(|_| {
+            let done = TABLE.post(current_id, Box::new(|table| *table = 1));
+            assert_eq!(TABLE.drain_mailbox(), 0);
+            done
+        });
+        assert_eq!(*TABLE.borrow(), 1);
+        assert!(done.wait().is_ok());
+        TABLE.freeze().unwrap();
+        let done = TABLE.post(current_id, Box::new(|table| *table = 2));
+        assert_eq!(TABLE.drain_mailbox(), 0);
+        assert!(done.wait().is_err());
+        assert_eq!(*TABLE.borrow(), 1);
+    }
+}
+
+mod cow {
+    use ref_thread_local::{RefThreadLocal, SetThreadLocal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+    fn build_table() -> Vec<u32> {
+        BUILDS.fetch_add(1, Ordering::SeqCst);
+        (0..1024).collect()
+    }
+
+    ref_thread_local! {
+        static managed(cow) TABLE: Vec<u32> = build_table();
+    }
+
+    impl TABLE {
+        fn value_address(&self) -> usize {
+            let table = self.borrow();
+            &*table as *const Vec<u32> as usize
+        }
+    }
+
+    #[test]
+    fn shared_until_written() {
+        let shared = TABLE.value_address();
+        let other = std::thread::spawn(|| TABLE.value_address()).join().unwrap();
+        assert_eq!(shared, other);
+        assert_eq!(BUILDS.load(Ordering::SeqCst), 1);
+
+        TABLE.borrow_mut()[0] = 42;
+        assert_ne!(TABLE.value_address(), shared);
+        assert_eq!(TABLE.borrow()[0], 42);
+        std::thread::spawn(|| assert_eq!(TABLE.borrow()[0], 0))
+            .join()
+            .unwrap();
+
+        assert_eq!(TABLE.take().unwrap()[0], 42);
+        assert_eq!(TABLE.borrow()[0], 0);
+        assert_eq!(BUILDS.load(Ordering::SeqCst), 1);
+    }
+}
+
+mod share {
+    use ref_thread_local::{RefThreadLocal, SetThreadLocal, ShareThreadLocal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static MERGED: AtomicUsize = AtomicUsize::new(0);
+
+    ref_thread_local! {
+        static managed SESSION: Vec<u32> = vec![1];
+        static managed COUNTER: usize = 0 => |count| { MERGED.fetch_add(count, Ordering::SeqCst); };
+        static once TOKEN: String;
+    }
+
+    #[test]
+    fn survives_destroy() {
+        let session = SESSION.share();
+        session.borrow_mut().push(2);
+        assert_eq!(*SESSION.borrow(), vec![1, 2]);
+
+        let borrowed = SESSION.borrow();
+        SESSION.destroy().unwrap();
+        assert!(session.is_detached());
+        assert_eq!(*borrowed, vec![1, 2]);
+        drop(borrowed);
+
+        assert_eq!(*SESSION.borrow(), vec![1]);
+        session.borrow_mut().push(3);
+        assert_eq!(*session.clone().borrow(), vec![1, 2, 3]);
+        assert_eq!(*SESSION.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn merged_with_last_handle() {
+        *COUNTER.borrow_mut() = 3;
+        let first = COUNTER.share();
+        let second = first.clone();
+        COUNTER.destroy().unwrap();
+        drop(first);
+        assert_eq!(MERGED.load(Ordering::SeqCst), 0);
+        drop(second);
+        assert_eq!(MERGED.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn borrow_count_is_shared() {
+        let session = SESSION.share();
+        let value = session.borrow_mut();
+        assert!(SESSION.try_borrow().is_err());
+        drop(value);
+        assert!(SESSION.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot move out of a shared value")]
+    fn take_while_shared() {
+        TOKEN.set("secret".to_string()).unwrap();
+        let _token = TOKEN.share();
+        TOKEN.take();
+    }
+}
+
+mod weak {
+    use ref_thread_local::{RefThreadLocal, SetThreadLocal, ShareThreadLocal};
+
+    ref_thread_local! {
+        static managed WINDOW: String = "main".to_string();
+        static once HANDLER: u32;
+    }
+
+    #[test]
+    fn upgrade_until_destroyed() {
+        let weak = WINDOW.downgrade();
+        assert_eq!(weak.generation(), WINDOW.generation());
+        assert_eq!(*weak.upgrade().unwrap(), "main");
+
+        let mut window = WINDOW.borrow_mut();
+        window.push('!');
+        assert!(weak.upgrade().is_none());
+        assert!(weak.is_alive());
+        drop(window);
+        assert_eq!(*weak.upgrade().unwrap(), "main!");
+
+        WINDOW.destroy().unwrap();
+        assert!(weak.upgrade().is_none());
+        WINDOW.initialize().unwrap();
+        assert!(weak.upgrade().is_none());
+        assert!(WINDOW.downgrade().upgrade().is_some());
+    }
+
+    #[test]
+    fn generation_changes_on_set() {
+        HANDLER.set(1).unwrap();
+        let weak = HANDLER.downgrade();
+        let generation = HANDLER.generation();
+        assert_eq!(HANDLER.take(), Some(1));
+        assert_eq!(HANDLER.generation(), generation + 1);
+        HANDLER.set(1).unwrap();
+        assert_eq!(HANDLER.generation(), generation + 2);
+        assert!(!weak.is_alive());
+    }
+
+    #[test]
+    fn per_thread() {
+        let weak = WINDOW.downgrade();
+        let other = std::thread::spawn(|| {
+            let weak = WINDOW.downgrade();
+            WINDOW.destroy().unwrap();
+            weak.is_alive()
+        });
+        assert!(!other.join().unwrap());
+        assert!(weak.is_alive());
+    }
+}
+
+mod pin {
+    use ref_thread_local::{MailboxThreadLocal, PinThreadLocal, RefThreadLocal, SetThreadLocal};
+    use std::marker::PhantomPinned;
+    use std::pin::Pin;
+
+    #[derive(Default)]
+    struct Node {
+        this: usize,
+        _pinned: PhantomPinned,
+    }
+
+    impl Node {
+        fn link(self: Pin<&mut Self>) {
+            let this = unsafe { self.get_unchecked_mut() };
+            this.this = this as *mut Node as usize;
+        }
+    }
+
+    ref_thread_local! {
+        static managed LIST: Node = Node::default();
+        static managed BUFFER: [u8; 4] = [0; 4];
+        static managed MERGED: u32 = 0 => |_| ();
+        static managed(mailbox) INBOX: u32 = 0;
+        static once SLOT: u32;
+    }
+
+    #[test]
+    fn stays_in_place() {
+        LIST.borrow_pin().as_mut().link();
+        assert_eq!(LIST.borrow().this, LIST.as_ptr() as usize);
+        assert!(LIST.try_borrow_mut().is_err());
+        assert!(LIST.try_borrow_pin().is_ok());
+
+        LIST.destroy().unwrap();
+        assert_eq!(LIST.borrow().this, 0);
+        assert!(LIST.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot move out of a pinned value")]
+    fn take_pinned() {
+        drop(LIST.borrow_pin());
+        LIST.take();
+    }
+
+    #[test]
+    #[should_panic(expected = "value is pinned")]
+    fn borrow_mut_pinned_once() {
+        SLOT.set(1).unwrap();
+        drop(SLOT.borrow_pin());
+        SLOT.borrow_mut();
+    }
+
+    #[test]
+    fn pinned_but_idle() {
+        drop(LIST.borrow_pin());
+        assert_eq!(ref_thread_local::destroy_all_on_current_thread(), Ok(()));
+        assert!(!LIST.is_initialized());
+    }
+
+    #[test]
+    fn merged_cannot_be_pinned() {
+        let err = MERGED.try_borrow_pin().unwrap_err();
+        assert_eq!(err.to_string(), "cannot pin a value with a merge function");
+        assert!(MERGED.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn pinned_mailbox() {
+        drop(INBOX.borrow_pin());
+        let completion = INBOX.post(std::thread::current().id(), Box::new(|value| *value = 1));
+        assert_eq!(INBOX.drain_mailbox(), 0);
+        assert!(completion.wait().is_err());
+        assert_eq!(*INBOX.borrow(), 0);
+    }
+
+    #[test]
+    fn raw_pointers() {
+        extern "C" fn fill(context: *mut u8, len: usize) {
+            let buffer = unsafe { std::slice::from_raw_parts_mut(context, len) };
+            buffer.fill(7);
+        }
+        fill(BUFFER.as_mut_ptr() as *mut u8, 4);
+        assert_eq!(*BUFFER.borrow(), [7; 4]);
+        assert_eq!(BUFFER.as_ptr(), &*BUFFER.borrow() as *const [u8; 4]);
+    }
+}
+
+mod typed {
+    use ref_thread_local::{register_typed_initializer, typed, RefThreadLocal, Typed};
+
+    fn push<T: 'static>(value: u32) -> Vec<u32> {
+        struct Log<T>(Vec<u32>, std::marker::PhantomData<T>);
+        impl<T> Default for Log<T> {
+            fn default() -> Self {
+                Log(Vec::new(), std::marker::PhantomData)
+            }
+        }
+        let log = typed::<Log<T>>();
+        log.borrow_mut().0.push(value);
+        let values = log.borrow().0.clone();
+        values
+    }
+
+    #[test]
+    fn one_value_per_type() {
+        assert_eq!(push::<u8>(1), vec![1]);
+        assert_eq!(push::<u16>(2), vec![2]);
+        assert_eq!(push::<u8>(3), vec![1, 3]);
+        std::thread::spawn(|| assert_eq!(push::<u8>(4), vec![4]))
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn borrow_rules() {
+        let value = typed::<String>().borrow_mut();
+        assert!(typed::<String>().try_borrow().is_err());
+        drop(value);
+        typed::<String>().destroy().unwrap();
+        assert!(!typed::<String>().is_initialized());
+    }
+
+    #[test]
+    fn registered_initializer() {
+        struct Port(u16);
+        register_typed_initializer(|| Port(8080));
+        let port = Typed::new(|| Port(0));
+        assert_eq!(port.borrow().0, 8080);
+    }
+}
+
+mod task_local {
+    use ref_thread_local::{RefThreadLocal, TaskLocalThreadLocal};
+    use std::future::{self, Future};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    task_local_managed! {
+        static TASK_NAME: &'static str;
+        pub static STEPS: Vec<u32> = Vec::new();
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Polls every task in turn until all of them are done.
+    fn run_all(mut tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        while !tasks.is_empty() {
+            tasks.retain_mut(|task| task.as_mut().poll(&mut cx).is_pending());
+        }
+    }
+
+    // A task that records its name and one step in each of its `count` polls.
+    fn steps(name: &'static str, count: u32) -> impl Future<Output = ()> {
+        let mut polls = 0;
+        STEPS.scope(
+            Vec::new(),
+            TASK_NAME.scope(
+                name,
+                future::poll_fn(move |cx| {
+                    assert_eq!(*TASK_NAME.borrow(), name);
+                    polls += 1;
+                    STEPS.borrow_mut().push(polls);
+                    assert_eq!(*STEPS.borrow(), (1..=polls).collect::<Vec<_>>());
+                    if polls == count {
+                        Poll::Ready(())
+                    } else {
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                }),
+            ),
+        )
+    }
+
+    #[test]
+    fn tasks_see_their_own_values() {
+        STEPS.borrow_mut().push(0);
+        run_all(vec![
+            Box::pin(steps("first", 3)),
+            Box::pin(steps("second", 5)),
+        ]);
+        assert!(!TASK_NAME.is_initialized());
+        assert_eq!(*STEPS.borrow(), vec![0]);
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let inner = TASK_NAME.scope(
+            "inner",
+            future::poll_fn(|_| Poll::Ready(*TASK_NAME.borrow())),
+        );
+        let mut inner = Box::pin(inner);
+        let outer = TASK_NAME.scope(
+            "outer",
+            future::poll_fn(move |cx| {
+                let name = inner.as_mut().poll(cx);
+                assert_eq!(*TASK_NAME.borrow(), "outer");
+                name
+            }),
+        );
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let name = Box::pin(outer)
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker));
+        assert_eq!(name, Poll::Ready("inner"));
+    }
+}
+
+mod shutdown {
+    use ref_thread_local::{
+        destroy_all_on_current_thread, shutdown, CellThreadLocal, RefThreadLocal, StructThreadLocal,
+    };
+    use std::sync::Mutex;
+
+    static DROPPED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    struct Logger(&'static str);
+
+    impl Drop for Logger {
+        fn drop(&mut self) {
+            if self.0 == "file" {
+                // closing the file updates statistics that were not used so far
+                let _ = STATS.borrow();
+            }
+            DROPPED.lock().unwrap().push(self.0);
+        }
+    }
+
+    ref_thread_local! {
+        static managed FILE: Logger = Logger("file");
+        static managed BUFFER: Logger = Logger("buffer");
+        static managed STATS: Logger = Logger("stats");
+        static managed BUSY: u32 = 0;
+        static cell COUNTER: u32 = 0;
+        static managed group STATE {
+            name: String = String::from("state"),
+            hits: u32 = 0,
+        }
+    }
+
+    #[test]
+    fn reverse_order_until_empty() {
+        std::thread::spawn(|| {
+            FILE.initialize().unwrap();
+            BUFFER.initialize().unwrap();
+            let busy = BUSY.borrow_mut();
+            assert_eq!(shutdown(), Err(vec!["BUSY"]));
+            drop(busy);
+            assert_eq!(*DROPPED.lock().unwrap(), vec!["buffer", "file", "stats"]);
+            assert!(!FILE.is_initialized() && !STATS.is_initialized());
+            assert_eq!(shutdown(), Ok(()));
+            assert!(!BUSY.is_initialized());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn cells_and_managed_groups() {
+        std::thread::spawn(|| {
+            COUNTER.set(1);
+            STATE.name.borrow_mut().push('!');
+            let hits = STATE.hits.borrow();
+            assert_eq!(destroy_all_on_current_thread(), Err(vec!["STATE"]));
+            assert!(!COUNTER.is_initialized() && STATE.is_initialized());
+            drop(hits);
+            assert_eq!(shutdown(), Ok(()));
+            assert!(!STATE.is_initialized());
+            assert_eq!(*STATE.name.borrow(), "state");
+        })
+        .join()
+        .unwrap();
+    }
+}